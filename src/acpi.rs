@@ -0,0 +1,336 @@
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use core::{mem, ptr, slice, str};
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{memory, println};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Physical address of the segment of the Extended BIOS Data Area.
+const EBDA_SEGMENT_ADDR: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe_0000;
+const BIOS_AREA_END: u64 = 0x10_0000;
+
+static RSDP: Once<Option<Rsdp>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No valid Root System Description Pointer was found in the BIOS area.
+    RsdpNotFound,
+    /// No table with the given signature is listed in the RSDT/XSDT.
+    TableNotFound([u8; 4]),
+    /// The checksum of the table with the given signature is wrong.
+    InvalidChecksum([u8; 4]),
+    /// The table is malformed in a way that prevents parsing it.
+    InvalidTable([u8; 4]),
+    /// The table could not be mapped into the virtual address space.
+    MappingFailed,
+}
+
+/// Root System Description Pointer, including the fields added in ACPI 2.0.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+/// Size of the RSDP as defined by ACPI 1.0.
+const RSDP_V1_SIZE: usize = 20;
+
+/// Header common to all System Description Tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    Other(u8),
+}
+
+/// Location of a register, as described by an ACPI Generic Address Structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// Access size, from 1 for byte up to 4 for quad word accesses, or 0 if undefined.
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Parses the 12 bytes of a Generic Address Structure, returning `None` for
+    /// a null address.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; 12] = bytes.try_into().ok()?;
+        let address = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        if address == 0 {
+            return None;
+        }
+
+        let address_space = match bytes[0] {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfiguration,
+            id => AddressSpace::Other(id),
+        };
+
+        Some(GenericAddress {
+            address_space,
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address,
+        })
+    }
+
+    /// Describes an I/O port of an older table, returning `None` for port zero.
+    fn io_port(port: u32) -> Option<Self> {
+        (port != 0).then_some(GenericAddress {
+            address_space: AddressSpace::SystemIo,
+            bit_width: 0,
+            bit_offset: 0,
+            access_size: 0,
+            address: u64::from(port),
+        })
+    }
+}
+
+/// Physical table which has been mapped and whose checksum has been validated.
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub header: SdtHeader,
+    phys_addr: PhysAddr,
+    virt_addr: VirtAddr,
+}
+
+impl Table {
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr
+    }
+
+    /// Returns the bytes following the header.
+    pub fn data(&self) -> &'static [u8] {
+        let header_size = mem::size_of::<SdtHeader>();
+        let table_size = self.header.length as usize;
+
+        unsafe {
+            slice::from_raw_parts((self.virt_addr + header_size).as_ptr(), table_size - header_size)
+        }
+    }
+}
+
+/// Looks up the table with the given signature, such as `b"APIC"` for the MADT.
+///
+/// Requires `memory::init` to have been called, since the tables are mapped on
+/// demand.
+pub fn find_table(signature: &[u8; 4]) -> Result<Table, AcpiError> {
+    tables()?
+        .filter_map(Result::ok)
+        .find(|table| &table.header.signature == signature)
+        .ok_or(AcpiError::TableNotFound(*signature))
+}
+
+/// Maps every table listed in the RSDT/XSDT.
+pub fn tables() -> Result<impl Iterator<Item = Result<Table, AcpiError>>, AcpiError> {
+    let (root, entry_size) = root_table()?;
+
+    Ok(root.data()
+        .chunks_exact(entry_size)
+        .map(move |entry| match entry_size {
+            8 => u64::from_le_bytes(entry.try_into().unwrap()),
+            _ => u64::from(u32::from_le_bytes(entry.try_into().unwrap())),
+        })
+        .map(|addr| map_table(PhysAddr::new(addr))))
+}
+
+/// Maps the root table, returning it with the size of its entries.
+fn root_table() -> Result<(Table, usize), AcpiError> {
+    let rsdp = RSDP.call_once(find_rsdp).ok_or(AcpiError::RsdpNotFound)?;
+
+    // prefer the XSDT with 64-bit entries if the firmware provides one
+    if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        Ok((map_table(PhysAddr::new(rsdp.xsdt_address))?, 8))
+    } else {
+        Ok((map_table(PhysAddr::new(u64::from(rsdp.rsdt_address)))?, 4))
+    }
+}
+
+/// Prints the ACPI tables and the parsed contents of the known ones.
+pub fn dump() {
+    let Some(rsdp) = RSDP.call_once(find_rsdp) else {
+        println!("ACPI: no RSDP found");
+        return;
+    };
+    let oem_id = rsdp.oem_id;
+    let revision = rsdp.revision;
+    println!("ACPI: RSDP revision {} from {}", revision, ascii(&oem_id));
+
+    match root_table() {
+        Ok((root, _)) => print_table(&root),
+        Err(error) => {
+            println!("ACPI: {:?}", error);
+        }
+    }
+    match tables() {
+        Ok(tables) => tables.for_each(|table| match table {
+            Ok(table) => print_table(&table),
+            Err(error) => {
+                println!("  {:?}", error);
+            }
+        }),
+        Err(error) => {
+            println!("ACPI: {:?}", error);
+        }
+    }
+
+    match fadt::parse() {
+        Ok(fadt) => {
+            if let Ok(dsdt) = fadt.dsdt() {
+                print_table(&dsdt);
+            }
+            println!("{:#x?}", fadt);
+        }
+        Err(error) => {
+            println!("FADT: {:?}", error);
+        }
+    }
+    match madt::parse() {
+        Ok(madt) => {
+            println!("{:#x?}", madt);
+        }
+        Err(error) => {
+            println!("MADT: {:?}", error);
+        }
+    }
+    match hpet::parse() {
+        Ok(hpet) => {
+            println!("{:#x?}", hpet);
+        }
+        Err(error) => {
+            println!("HPET: {:?}", error);
+        }
+    }
+    match mcfg::parse() {
+        Ok(regions) => {
+            println!("{:#x?}", regions);
+        }
+        Err(error) => {
+            println!("MCFG: {:?}", error);
+        }
+    }
+}
+
+fn print_table(table: &Table) {
+    let SdtHeader { signature, length, revision, oem_id, oem_table_id, .. } = table.header;
+
+    println!(
+        "  {} {:#x} length {} revision {} {} {}",
+        ascii(&signature),
+        table.phys_addr().as_u64(),
+        length,
+        revision,
+        ascii(&oem_id),
+        ascii(&oem_table_id),
+    );
+}
+
+/// Returns the identifier, or `?` if it is not valid ASCII.
+fn ascii(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).map(|id| id.trim_end()).unwrap_or("?")
+}
+
+/// Maps the table at the given physical address and validates its checksum.
+fn map_table(addr: PhysAddr) -> Result<Table, AcpiError> {
+    let header_size = mem::size_of::<SdtHeader>() as u64;
+    let virt_addr = memory::map_physical(addr, header_size).map_err(|_| AcpiError::MappingFailed)?;
+    let header = unsafe { ptr::read_unaligned(virt_addr.as_ptr::<SdtHeader>()) };
+
+    let length = u64::from(header.length);
+    if length < header_size {
+        return Err(AcpiError::InvalidTable(header.signature));
+    }
+
+    memory::map_physical(addr, length).map_err(|_| AcpiError::MappingFailed)?;
+    let bytes = unsafe { slice::from_raw_parts(virt_addr.as_ptr::<u8>(), length as usize) };
+    if !checksum_valid(bytes) {
+        return Err(AcpiError::InvalidChecksum(header.signature));
+    }
+
+    Ok(Table {
+        header,
+        phys_addr: addr,
+        virt_addr,
+    })
+}
+
+/// Searches the first KiB of the EBDA and the BIOS read-only memory area for
+/// the RSDP.
+fn find_rsdp() -> Option<Rsdp> {
+    memory::map_physical(PhysAddr::new(0), BIOS_AREA_END).ok()?;
+
+    let ebda_segment = unsafe {
+        ptr::read_unaligned(memory::phys_to_virt(PhysAddr::new(EBDA_SEGMENT_ADDR)).as_ptr::<u16>())
+    };
+    let ebda_start = u64::from(ebda_segment) << 4;
+
+    (ebda_start..ebda_start + 1024)
+        .chain(BIOS_AREA_START..BIOS_AREA_END)
+        .step_by(16)
+        .find_map(|addr| unsafe { read_rsdp(PhysAddr::new(addr)) })
+}
+
+/// Reads the RSDP at the given address if its signature and checksums match.
+unsafe fn read_rsdp(addr: PhysAddr) -> Option<Rsdp> {
+    let ptr = memory::phys_to_virt(addr).as_ptr::<u8>();
+    let v1_bytes = unsafe { slice::from_raw_parts(ptr, RSDP_V1_SIZE) };
+
+    if &v1_bytes[..8] != RSDP_SIGNATURE || !checksum_valid(v1_bytes) {
+        return None;
+    }
+
+    let mut rsdp = unsafe { ptr::read_unaligned(ptr as *const Rsdp) };
+    if rsdp.revision < 2 {
+        // fields of later revisions are garbage
+        rsdp.length = RSDP_V1_SIZE as u32;
+        rsdp.xsdt_address = 0;
+        return Some(rsdp);
+    }
+
+    let length = rsdp.length as usize;
+    if length < mem::size_of::<Rsdp>() {
+        return None;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(ptr, length) };
+    checksum_valid(bytes).then_some(rsdp)
+}
+
+/// ACPI structures are valid if all of their bytes add up to zero.
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
@@ -0,0 +1,101 @@
+use super::{AcpiError, GenericAddress, Table};
+use x86_64::PhysAddr;
+
+const SIGNATURE: &[u8; 4] = b"FACP";
+
+/// Set in `iapc_boot_arch` if the system has an 8042 keyboard controller.
+pub const BOOT_ARCH_8042: u16 = 1 << 1;
+/// Set in `flags` if the reset register is supported.
+pub const FLAG_RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+/// Fixed ACPI Description Table, describing the power management hardware.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub firmware_ctrl: u64,
+    /// Physical address of the Differentiated System Description Table.
+    pub dsdt: u64,
+    pub preferred_pm_profile: u8,
+    /// Interrupt used by the ACPI hardware, as ISA IRQ or global system interrupt.
+    pub sci_interrupt: u16,
+    /// Port to which `acpi_enable` or `acpi_disable` is written to switch modes.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: Option<GenericAddress>,
+    pub pm1b_event_block: Option<GenericAddress>,
+    pub pm1a_control_block: Option<GenericAddress>,
+    pub pm1b_control_block: Option<GenericAddress>,
+    pub pm_timer_block: Option<GenericAddress>,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm_timer_length: u8,
+    /// CMOS register holding the century, zero if not supported.
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    /// Maps the DSDT referenced by this table.
+    pub fn dsdt(&self) -> Result<Table, AcpiError> {
+        super::map_table(PhysAddr::new(self.dsdt))
+    }
+}
+
+/// Finds and parses the FADT.
+///
+/// Fields beyond the end of tables of older revisions are zero, and the 64-bit
+/// addresses are preferred over their 32-bit counterparts.
+pub fn parse() -> Result<Fadt, AcpiError> {
+    let table = super::find_table(SIGNATURE)?;
+    let data = table.data();
+
+    if data.len() < 80 {
+        return Err(AcpiError::InvalidTable(*SIGNATURE));
+    }
+
+    let read_u8 = |offset: usize| data.get(offset).copied().unwrap_or(0);
+    let read_u16 = |offset: usize| u16::from_le_bytes([read_u8(offset), read_u8(offset + 1)]);
+    let read_u32 = |offset: usize| u32::from_le_bytes([0, 1, 2, 3].map(|i| read_u8(offset + i)));
+    let read_u64 = |offset: usize| u64::from(read_u32(offset)) | u64::from(read_u32(offset + 4)) << 32;
+    let read_address = |offset: usize| data.get(offset..offset + 12).and_then(GenericAddress::parse);
+
+    // blocks are given both as I/O ports and as extended generic addresses
+    let block = |port_offset: usize, extended_offset: usize| {
+        read_address(extended_offset).or_else(|| GenericAddress::io_port(read_u32(port_offset)))
+    };
+
+    let firmware_ctrl = match read_u64(96) {
+        0 => u64::from(read_u32(0)),
+        address => address,
+    };
+    let dsdt = match read_u64(104) {
+        0 => u64::from(read_u32(4)),
+        address => address,
+    };
+
+    Ok(Fadt {
+        firmware_ctrl,
+        dsdt,
+        preferred_pm_profile: read_u8(9),
+        sci_interrupt: read_u16(10),
+        smi_command_port: read_u32(12),
+        acpi_enable: read_u8(16),
+        acpi_disable: read_u8(17),
+        pm1a_event_block: block(20, 112),
+        pm1b_event_block: block(24, 124),
+        pm1a_control_block: block(28, 136),
+        pm1b_control_block: block(32, 148),
+        pm_timer_block: block(40, 172),
+        pm1_event_length: read_u8(52),
+        pm1_control_length: read_u8(53),
+        pm_timer_length: read_u8(55),
+        century: read_u8(72),
+        iapc_boot_arch: read_u16(73),
+        flags: read_u32(76),
+        reset_register: read_address(80),
+        reset_value: read_u8(92),
+    })
+}
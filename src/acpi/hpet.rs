@@ -0,0 +1,47 @@
+use super::{AcpiError, AddressSpace, GenericAddress};
+
+const SIGNATURE: &[u8; 4] = b"HPET";
+
+/// High Precision Event Timer description table.
+#[derive(Debug, Clone, Copy)]
+pub struct HpetTable {
+    pub hardware_revision: u8,
+    pub comparator_count: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement_capable: bool,
+    pub pci_vendor_id: u16,
+    /// Physical address of the register block.
+    pub base_address: u64,
+    pub hpet_number: u8,
+    /// Minimum counter ticks between interrupts in periodic mode.
+    pub min_tick: u16,
+}
+
+/// Finds and parses the HPET table.
+pub fn parse() -> Result<HpetTable, AcpiError> {
+    let table = super::find_table(SIGNATURE)?;
+    let data = table.data();
+    let invalid = AcpiError::InvalidTable(*SIGNATURE);
+
+    if data.len() < 20 {
+        return Err(invalid);
+    }
+
+    let base_address = GenericAddress::parse(&data[4..16]).ok_or(invalid)?;
+    if base_address.address_space != AddressSpace::SystemMemory {
+        return Err(invalid);
+    }
+
+    let block_id = u32::from_le_bytes(data[0..4].try_into().unwrap());
+
+    Ok(HpetTable {
+        hardware_revision: block_id as u8,
+        comparator_count: ((block_id >> 8) & 0x1f) as u8 + 1,
+        counter_64bit: block_id & (1 << 13) != 0,
+        legacy_replacement_capable: block_id & (1 << 15) != 0,
+        pci_vendor_id: (block_id >> 16) as u16,
+        base_address: base_address.address,
+        hpet_number: data[16],
+        min_tick: u16::from_le_bytes([data[17], data[18]]),
+    })
+}
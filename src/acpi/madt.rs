@@ -0,0 +1,161 @@
+use alloc::vec::Vec;
+use super::AcpiError;
+
+const SIGNATURE: &[u8; 4] = b"APIC";
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// Multiple APIC Description Table, listing the interrupt controllers of the
+/// system.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// Set if the system also has dual 8259 PICs which have to be disabled.
+    pub pc_at_compatible: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub interrupt_overrides: Vec<InterruptOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    /// First global system interrupt handled by this I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Describes an ISA IRQ which is not identity mapped to a global system
+/// interrupt, or which uses a non-standard polarity or trigger mode.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source_irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicNmi {
+    /// Processor ID to which this applies, `0xff` meaning all processors.
+    pub processor_id: u8,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+    /// Local interrupt pin (LINT0 or LINT1) to which the NMI is connected.
+    pub lint: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ConformsToBus,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    ConformsToBus,
+    Edge,
+    Level,
+}
+
+/// Decodes the polarity and trigger mode of MPS INTI flags.
+fn decode_inti_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ConformsToBus,
+    };
+    let trigger_mode = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::ConformsToBus,
+    };
+
+    (polarity, trigger_mode)
+}
+
+/// Finds and parses the MADT.
+pub fn parse() -> Result<Madt, AcpiError> {
+    let table = super::find_table(SIGNATURE)?;
+    let data = table.data();
+    let invalid = AcpiError::InvalidTable(*SIGNATURE);
+
+    if data.len() < 8 {
+        return Err(invalid);
+    }
+
+    let read_u16 = |bytes: &[u8], offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let read_u32 = |bytes: &[u8], offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let mut madt = Madt {
+        local_apic_address: u64::from(read_u32(data, 0)),
+        pc_at_compatible: read_u32(data, 4) & 1 != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        interrupt_overrides: Vec::new(),
+        local_apic_nmis: Vec::new(),
+    };
+
+    let mut entries = &data[8..];
+    while entries.len() >= 2 {
+        let (entry_type, length) = (entries[0], usize::from(entries[1]));
+        if length < 2 || length > entries.len() {
+            return Err(invalid);
+        }
+
+        let entry = &entries[..length];
+        match (entry_type, length) {
+            (ENTRY_LOCAL_APIC, 8..) => madt.processors.push(Processor {
+                processor_id: entry[2],
+                apic_id: entry[3],
+                enabled: read_u32(entry, 4) & 1 != 0,
+            }),
+            (ENTRY_IO_APIC, 12..) => madt.io_apics.push(IoApic {
+                id: entry[2],
+                address: read_u32(entry, 4),
+                gsi_base: read_u32(entry, 8),
+            }),
+            (ENTRY_INTERRUPT_OVERRIDE, 10..) => {
+                let (polarity, trigger_mode) = decode_inti_flags(read_u16(entry, 8));
+                madt.interrupt_overrides.push(InterruptOverride {
+                    bus: entry[2],
+                    source_irq: entry[3],
+                    gsi: read_u32(entry, 4),
+                    polarity,
+                    trigger_mode,
+                });
+            }
+            (ENTRY_LOCAL_APIC_NMI, 6..) => {
+                let (polarity, trigger_mode) = decode_inti_flags(read_u16(entry, 3));
+                madt.local_apic_nmis.push(LocalApicNmi {
+                    processor_id: entry[2],
+                    polarity,
+                    trigger_mode,
+                    lint: entry[5],
+                });
+            }
+            (ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE, 12..) => {
+                madt.local_apic_address = u64::from_le_bytes(entry[4..12].try_into().unwrap());
+            }
+            _ => {}
+        }
+
+        entries = &entries[length..];
+    }
+
+    Ok(madt)
+}
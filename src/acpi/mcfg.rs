@@ -0,0 +1,38 @@
+use alloc::vec::Vec;
+use super::AcpiError;
+
+const SIGNATURE: &[u8; 4] = b"MCFG";
+
+/// Size of the reserved field in front of the entries.
+const RESERVED_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+/// Memory mapped configuration space of a PCI segment group.
+#[derive(Debug, Clone, Copy)]
+pub struct EcamRegion {
+    /// Physical address of the configuration space of `start_bus`.
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Finds the MCFG and returns the configuration space regions it describes.
+pub fn parse() -> Result<Vec<EcamRegion>, AcpiError> {
+    let table = super::find_table(SIGNATURE)?;
+    let data = table.data();
+
+    if data.len() < RESERVED_SIZE {
+        return Err(AcpiError::InvalidTable(*SIGNATURE));
+    }
+
+    Ok(data[RESERVED_SIZE..]
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| EcamRegion {
+            base_address: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            segment: u16::from_le_bytes([entry[8], entry[9]]),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+        .collect())
+}
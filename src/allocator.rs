@@ -1,18 +1,38 @@
 pub mod bump;
 pub mod linked_list;
 pub mod fixed_size;
+pub mod buddy;
+pub mod debug;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::structures::paging::mapper::MapToError;
 use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
 use x86_64::VirtAddr;
 use spin::{Mutex, MutexGuard};
-use fixed_size::FixedSizeBlockAllocator;
+use crate::memory;
+
+#[cfg(not(feature = "buddy-allocator"))]
+use fixed_size::FixedSizeBlockAllocator as HeapAllocator;
+#[cfg(feature = "buddy-allocator")]
+use buddy::BuddyAllocator as HeapAllocator;
 
 pub const HEAP_START: usize = 0x4444_4444_0000;
+/// Size of the heap mapped by `init_heap`.
 pub const HEAP_SIZE: usize = 100 * 1024;
+/// Default upper bound for the heap size when growing it on demand.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// End of the currently mapped heap region.
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START);
+/// Size which the heap is not allowed to grow beyond.
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
 
+#[cfg_attr(not(feature = "debug-heap"), global_allocator)]
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+#[cfg(feature = "debug-heap")]
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static DEBUG_ALLOCATOR: debug::DebugAllocator<Locked<HeapAllocator>> = debug::DebugAllocator::new(&ALLOCATOR);
 
 struct Locked<A> {
     inner: Mutex<A>,
@@ -28,9 +48,85 @@ impl<A> Locked<A> {
     pub fn lock<'a>(&'a self) -> MutexGuard<'a, A> {
         self.inner.lock()
     }
+
+    /// Runs `f` on the locked allocator with interrupts disabled.
+    ///
+    /// This keeps the scheduler from preempting a thread while it holds the
+    /// heap lock, which would deadlock the next thread trying to allocate.
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut A) -> R) -> R {
+        x86_64::instructions::interrupts::without_interrupts(|| f(&mut self.lock()))
+    }
+}
+
+/// Maps the initial heap region and initializes the global allocator with it.
+///
+/// Requires `memory::init` to have been called, since the heap keeps using the
+/// kernel memory mapper to grow later on.
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    memory::with_memory(|mapper, frame_allocator| {
+        map_heap_pages(HEAP_START, HEAP_SIZE, mapper, frame_allocator)
+    }).ok_or(MapToError::FrameAllocationFailed)??;
+
+    HEAP_END.store(HEAP_START + HEAP_SIZE, Ordering::SeqCst);
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// Returns the number of bytes currently mapped for the heap.
+pub fn heap_size() -> usize {
+    HEAP_END.load(Ordering::SeqCst) - HEAP_START
+}
+
+/// Sets the size which the heap is allowed to grow up to.
+///
+/// Memory which is already mapped is never given back, so a limit below the
+/// current heap size only prevents further growth.
+pub fn set_heap_limit(size: usize) {
+    HEAP_LIMIT.store(size, Ordering::SeqCst);
+}
+
+/// Maps additional pages at the end of the heap, so that at least `min_size`
+/// new bytes become available.
+///
+/// Returns the start address and size of the newly mapped region, which might
+/// be smaller than `min_size` if the physical memory ran out while mapping.
+fn grow_heap(min_size: usize) -> Option<(usize, usize)> {
+    use x86_64::structures::paging::PageSize;
+
+    let heap_end = HEAP_END.load(Ordering::SeqCst);
+    let heap_limit = HEAP_START + HEAP_LIMIT.load(Ordering::SeqCst);
+
+    // grow at least by the initial heap size to avoid mapping single pages
+    let size = align_up(min_size.max(HEAP_SIZE), Size4KiB::SIZE as usize);
+    let size = size.min(heap_limit.saturating_sub(heap_end));
+    if size < min_size {
+        return None;
+    }
+
+    let mut mapped = 0;
+    memory::with_memory(|mapper, frame_allocator| {
+        while mapped < size {
+            let page_size = Size4KiB::SIZE as usize;
+            if map_heap_pages(heap_end + mapped, page_size, mapper, frame_allocator).is_err() {
+                break;
+            }
+            mapped += page_size;
+        }
+    })?;
+
+    if mapped == 0 {
+        return None;
+    }
+
+    HEAP_END.store(heap_end + mapped, Ordering::SeqCst);
+    Some((heap_end, mapped))
 }
 
-pub fn init_heap<M, A>(mapper: &mut M, allocator: &mut A) -> Result<(), MapToError<Size4KiB>>
+fn map_heap_pages<M, A>(start: usize, size: usize, mapper: &mut M, allocator: &mut A) -> Result<(), MapToError<Size4KiB>>
 where
     M: Mapper<Size4KiB>,
     A: FrameAllocator<Size4KiB>,
@@ -38,8 +134,8 @@ where
     use x86_64::structures::paging::{Page, PageTableFlags};
 
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_start = VirtAddr::new(start as u64);
+        let heap_end = heap_start + size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -54,11 +150,94 @@ where
         }
     }
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    Ok(())
+}
+
+/// Maximum number of free lists reported in `Stats`.
+pub const MAX_FREE_LISTS: usize = 32;
+
+/// Snapshot of the state of a heap allocator.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Bytes currently handed out to callers.
+    pub bytes_in_use: usize,
+    /// Highest value `bytes_in_use` has ever reached.
+    pub peak_bytes_in_use: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// Size of the largest contiguous free block.
+    pub largest_free_block: usize,
+    free_list_lengths: [usize; MAX_FREE_LISTS],
+    free_list_count: usize,
+}
+
+impl Stats {
+    fn new(usage: &Usage, largest_free_block: usize) -> Self {
+        Stats {
+            bytes_in_use: usage.bytes_in_use,
+            peak_bytes_in_use: usage.peak_bytes_in_use,
+            allocations: usage.allocations,
+            deallocations: usage.deallocations,
+            largest_free_block,
+            free_list_lengths: [0; MAX_FREE_LISTS],
+            free_list_count: 0,
+        }
     }
 
-    Ok(())
+    fn push_free_list(&mut self, length: usize) {
+        self.free_list_lengths[self.free_list_count] = length;
+        self.free_list_count += 1;
+    }
+
+    /// Returns the number of blocks in each free list of the allocator.
+    ///
+    /// For the fixed size block allocator the lists are in the order of
+    /// `fixed_size::BLOCK_SIZES`, for the buddy allocator they are indexed by
+    /// block order.
+    pub fn free_list_lengths(&self) -> &[usize] {
+        &self.free_list_lengths[..self.free_list_count]
+    }
+}
+
+/// Allocators which are able to report statistics about their state.
+pub trait HeapStats {
+    fn stats(&self) -> Stats;
+}
+
+/// Returns the statistics of the global heap allocator.
+pub fn stats() -> Stats {
+    ALLOCATOR.with_lock(|allocator| allocator.stats())
+}
+
+/// Allocation counters kept by every allocator.
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    bytes_in_use: usize,
+    peak_bytes_in_use: usize,
+    allocations: usize,
+    deallocations: usize,
+}
+
+impl Usage {
+    const fn new() -> Self {
+        Usage {
+            bytes_in_use: 0,
+            peak_bytes_in_use: 0,
+            allocations: 0,
+            deallocations: 0,
+        }
+    }
+
+    fn record_allocation(&mut self, size: usize) {
+        self.bytes_in_use += size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+        self.allocations += 1;
+    }
+
+    fn record_deallocation(&mut self, size: usize) {
+        self.bytes_in_use -= size;
+        self.deallocations += 1;
+    }
 }
 
 /// Align the given address `addr` upwards to alignment `align`.
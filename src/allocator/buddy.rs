@@ -0,0 +1,368 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use super::{HeapStats, Locked, Stats, Usage};
+
+/// Size of the smallest block, which has to be able to hold a `FreeBlock`.
+const MIN_BLOCK_SIZE: usize = 32;
+/// Number of block sizes, the largest block being 64 MiB.
+const ORDERS: usize = 22;
+/// Size of the heap covered by the free bitmaps, memory beyond it stays unused.
+const MAX_HEAP_SIZE: usize = MIN_BLOCK_SIZE << (ORDERS - 1);
+/// Number of words of the free bitmaps of all orders.
+const BITMAP_WORDS: usize = bitmap_offset(ORDERS);
+
+/// Header written to the start of every free block.
+struct FreeBlock {
+    prev: *mut FreeBlock,
+    next: *mut FreeBlock,
+}
+
+/// Power-of-two buddy allocator.
+///
+/// Every block of order `n` has a size of `MIN_BLOCK_SIZE << n` and is aligned to
+/// its size, so the buddy of a block is found by flipping a single address bit.
+/// Free blocks of every order are kept in doubly linked lists, which makes both
+/// splitting and merging take O(log n) steps.
+///
+/// Whether a block is free is tracked in a bitmap per order outside of the
+/// heap, since the memory of an allocated block may contain anything.
+pub struct BuddyAllocator {
+    free_lists: [*mut FreeBlock; ORDERS],
+    free_bitmap: [u64; BITMAP_WORDS],
+    heap_start: usize,
+    heap_end: usize,
+    usage: Usage,
+}
+
+// the free lists only point into the heap, which is owned by the allocator
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// Creates an empty BuddyAllocator.
+    pub const fn new() -> Self {
+        BuddyAllocator {
+            free_lists: [ptr::null_mut(); ORDERS],
+            free_bitmap: [0; BITMAP_WORDS],
+            heap_start: 0,
+            heap_end: 0,
+            usage: Usage::new(),
+        }
+    }
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    ///
+    /// The memory from `heap_start` to `heap_start + heap_size` must be mapped
+    /// writable and must not be used by anything but the allocator for as long
+    /// as it hands out blocks from it.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.free_lists = [ptr::null_mut(); ORDERS];
+        self.free_bitmap.fill(0);
+        self.heap_start = heap_start;
+        self.heap_end = heap_start;
+
+        unsafe {
+            self.extend(heap_start, heap_size);
+        }
+    }
+
+    /// Adds a newly mapped memory region directly following the heap.
+    ///
+    /// # Safety
+    ///
+    /// The region from `addr` to `addr + size` must be mapped writable and
+    /// unused. It belongs to the allocator from then on.
+    pub unsafe fn extend(&mut self, addr: usize, size: usize) {
+        assert_eq!(addr, self.heap_end, "Heap can only be extended at its end");
+
+        let mut start = super::align_up(addr, MIN_BLOCK_SIZE);
+        let end = (addr + size).min(self.heap_start + MAX_HEAP_SIZE) & !(MIN_BLOCK_SIZE - 1);
+        self.heap_end = addr + size;
+
+        // split the region into the largest blocks which are aligned to their size
+        while start < end {
+            let order = (0..ORDERS)
+                .rev()
+                .find(|&order| {
+                    let block_size = block_size(order);
+                    start.is_multiple_of(block_size) && start + block_size <= end
+                })
+                .unwrap();
+
+            self.free_block(start, order);
+            start += block_size(order);
+        }
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let order = order_for(&layout)?;
+
+        loop {
+            if let Some(block) = self.allocate_block(order) {
+                self.usage.record_allocation(layout.size());
+                return NonNull::new(block as *mut u8);
+            }
+
+            // grow up to the end of the next block aligned to its size, the
+            // memory in front of it becomes smaller blocks
+            let size = block_size(order);
+            let min_size = super::align_up(self.heap_end, size) + size - self.heap_end;
+            let (region_start, region_size) = super::grow_heap(min_size)?;
+            unsafe {
+                self.extend(region_start, region_size);
+            }
+        }
+    }
+
+    /// Returns a block to the free lists, merging it with its free buddies.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` for the same `layout` and
+    /// must not be used afterwards, since the block may be merged right away.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let order = order_for(&layout).expect("Deallocated layout can't have been allocated");
+        self.usage.record_deallocation(layout.size());
+
+        self.free_block(ptr.addr().get(), order);
+    }
+
+    /// Takes a block of the given order, splitting a larger one if needed.
+    fn allocate_block(&mut self, order: usize) -> Option<usize> {
+        let mut current = (order..ORDERS).find(|&order| !self.free_lists[order].is_null())?;
+        let block = self.free_lists[current] as usize;
+        self.remove(block, current);
+
+        // return the upper halves to the free lists until the block fits
+        while current > order {
+            current -= 1;
+            self.push(block + block_size(current), current);
+        }
+
+        Some(block)
+    }
+
+    /// Frees the given block and merges it with its buddy for as long as the
+    /// buddy is free as well.
+    fn free_block(&mut self, mut block: usize, mut order: usize) {
+        while order + 1 < ORDERS {
+            let buddy = block ^ block_size(order);
+            if !self.is_free(buddy, order) {
+                break;
+            }
+
+            self.remove(buddy, order);
+            block = block.min(buddy);
+            order += 1;
+        }
+
+        self.push(block, order);
+    }
+
+    /// Checks whether the block at `addr` is in the free list of the given order.
+    fn is_free(&self, addr: usize, order: usize) -> bool {
+        let end = self.heap_end.min(self.heap_start + MAX_HEAP_SIZE);
+        if addr < self.heap_start || addr + block_size(order) > end {
+            return false;
+        }
+
+        let (word, bit) = self.bitmap_position(addr, order);
+        self.free_bitmap[word] & bit != 0
+    }
+
+    /// Returns the index of the bitmap word and the bit of the given block.
+    fn bitmap_position(&self, addr: usize, order: usize) -> (usize, u64) {
+        let index = (addr - self.heap_start) / block_size(order);
+        (bitmap_offset(order) + index / 64, 1 << (index % 64))
+    }
+
+    fn push(&mut self, addr: usize, order: usize) {
+        let block = addr as *mut FreeBlock;
+        let next = self.free_lists[order];
+
+        unsafe {
+            block.write(FreeBlock {
+                prev: ptr::null_mut(),
+                next,
+            });
+
+            if !next.is_null() {
+                (*next).prev = block;
+            }
+        }
+
+        self.free_lists[order] = block;
+
+        let (word, bit) = self.bitmap_position(addr, order);
+        self.free_bitmap[word] |= bit;
+    }
+
+    fn remove(&mut self, addr: usize, order: usize) {
+        let block = addr as *mut FreeBlock;
+
+        unsafe {
+            let FreeBlock { prev, next } = block.read();
+
+            if prev.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*prev).next = next;
+            }
+
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+
+        let (word, bit) = self.bitmap_position(addr, order);
+        self.free_bitmap[word] &= !bit;
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapStats for BuddyAllocator {
+    fn stats(&self) -> Stats {
+        let mut order_lists = [0; ORDERS];
+        let mut largest_free_block = 0;
+
+        for (order, &head) in self.free_lists.iter().enumerate() {
+            let mut current = head;
+            while !current.is_null() {
+                order_lists[order] += 1;
+                current = unsafe { (*current).next };
+            }
+
+            if order_lists[order] > 0 {
+                largest_free_block = block_size(order);
+            }
+        }
+
+        let mut stats = Stats::new(&self.usage, largest_free_block);
+        for length in order_lists {
+            stats.push_free_list(length);
+        }
+        stats
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BuddyAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.with_lock(|allocator| allocator.allocate(layout)) {
+            Some(ptr) => ptr.as_ptr(),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_lock(|allocator| unsafe {
+            allocator.deallocate(NonNull::new_unchecked(ptr), layout);
+        });
+    }
+}
+
+const fn block_size(order: usize) -> usize {
+    MIN_BLOCK_SIZE << order
+}
+
+/// Returns the index of the first bitmap word of the given order, every order
+/// taking at least one word.
+const fn bitmap_offset(order: usize) -> usize {
+    let mut offset = 0;
+    let mut current = 0;
+    while current < order {
+        offset += (MAX_HEAP_SIZE / block_size(current)).div_ceil(64);
+        current += 1;
+    }
+    offset
+}
+
+/// Choose the order of the smallest block which fits the given layout.
+fn order_for(layout: &Layout) -> Option<usize> {
+    let size = layout.size()
+        .max(layout.align())
+        .max(MIN_BLOCK_SIZE)
+        .checked_next_power_of_two()?;
+    let order = (size / MIN_BLOCK_SIZE).trailing_zeros() as usize;
+
+    (order < ORDERS).then_some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAP_SIZE: usize = 4096 * 4;
+    /// Order of a single block covering the whole test heap.
+    const HEAP_ORDER: usize = (HEAP_SIZE / MIN_BLOCK_SIZE).trailing_zeros() as usize;
+
+    #[repr(align(4096))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    /// Runs `f` on an allocator covering a fixed backing array.
+    ///
+    /// The tests use `allocate_block` and `free_block` directly, so the heap is
+    /// never grown.
+    fn with_allocator<F: FnOnce(&mut BuddyAllocator)>(f: F) {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        // too large for the stack because of the bitmaps
+        static ALLOCATOR: Locked<BuddyAllocator> = Locked::new(BuddyAllocator::new());
+
+        let mut allocator = ALLOCATOR.lock();
+        unsafe {
+            allocator.init(&raw mut HEAP.0 as usize, HEAP_SIZE);
+        }
+        f(&mut allocator);
+    }
+
+    #[test_case]
+    fn test_split_and_merge() {
+        with_allocator(|allocator| {
+            let a = allocator.allocate_block(1).unwrap();
+            let b = allocator.allocate_block(1).unwrap();
+
+            // splitting hands out the two buddies of the same parent block
+            assert_eq!(a ^ b, block_size(1));
+
+            allocator.free_block(a, 1);
+            allocator.free_block(b, 1);
+
+            // everything merged back, so the whole heap is one block again
+            assert_eq!(allocator.allocate_block(HEAP_ORDER), Some(allocator.heap_start));
+        });
+    }
+
+    #[test_case]
+    fn test_allocated_buddy_is_not_merged() {
+        with_allocator(|allocator| {
+            let a = allocator.allocate_block(1).unwrap();
+            let b = allocator.allocate_block(1).unwrap();
+
+            // data of an allocated block which looks like a free block header
+            let head = allocator.free_lists[1] as usize;
+            unsafe {
+                (b as *mut [usize; 8]).write([head; 8]);
+            }
+            allocator.free_block(a, 1);
+
+            assert!(allocator.allocate_block(HEAP_ORDER).is_none());
+        });
+    }
+
+    #[test_case]
+    fn test_alignment() {
+        with_allocator(|allocator| {
+            let layout = Layout::from_size_align(8, 1024).unwrap();
+            let order = order_for(&layout).unwrap();
+            allocator.allocate_block(0).unwrap();
+            let block = allocator.allocate_block(order).unwrap();
+
+            assert_eq!(block % 1024, 0);
+        });
+    }
+}
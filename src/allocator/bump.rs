@@ -1,11 +1,12 @@
 use alloc::alloc::{GlobalAlloc, Layout};
-use super::Locked;
+use super::{HeapStats, Locked, Stats, Usage};
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     next: usize,
     allocations: usize,
+    usage: Usage,
 }
 
 impl BumpAllocator {
@@ -15,9 +16,17 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            usage: Usage::new(),
         }
     }
 
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    ///
+    /// The memory from `heap_start` to `heap_start + heap_size` must be mapped
+    /// writable and unused for the lifetime of the allocator. This method must
+    /// be called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
@@ -25,33 +34,47 @@ impl BumpAllocator {
     }
 }
 
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapStats for BumpAllocator {
+    fn stats(&self) -> Stats {
+        Stats::new(&self.usage, self.heap_end - self.next)
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         use core::ptr;
 
-        let mut allocator = self.lock();
-
-        let alloc_start = super::align_up(allocator.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
-        };
+        self.with_lock(|allocator| {
+            let alloc_start = super::align_up(allocator.next, layout.align());
+            let alloc_end = match alloc_start.checked_add(layout.size()) {
+                Some(end) => end,
+                None => return ptr::null_mut(),
+            };
 
-        if alloc_end > allocator.heap_end {
-            ptr::null_mut()
-        } else {
-            allocator.next = alloc_end;
-            allocator.allocations += 1;
-            alloc_start as *mut u8
-        }
+            if alloc_end > allocator.heap_end {
+                ptr::null_mut()
+            } else {
+                allocator.next = alloc_end;
+                allocator.allocations += 1;
+                allocator.usage.record_allocation(layout.size());
+                alloc_start as *mut u8
+            }
+        })
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut allocator = self.lock();
-
-        allocator.allocations -= 1;
-        if allocator.allocations == 0 {
-            allocator.next = allocator.heap_start;
-        }
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        self.with_lock(|allocator| {
+            allocator.usage.record_deallocation(layout.size());
+            allocator.allocations -= 1;
+            if allocator.allocations == 0 {
+                allocator.next = allocator.heap_start;
+            }
+        });
     }
 }
\ No newline at end of file
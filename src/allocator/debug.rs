@@ -0,0 +1,162 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, ptr, slice};
+use crate::serial_println;
+
+/// Number of guard bytes placed before and after every allocation.
+const GUARD_SIZE: usize = 16;
+/// Space left at the start of every block for the free list node which the
+/// underlying allocator writes into freed memory, so that it does not overwrite
+/// the allocation header.
+const METADATA_RESERVE: usize = 32;
+
+const GUARD_BYTE: u8 = 0xfd;
+const UNINIT_BYTE: u8 = 0xcd;
+const POISON_BYTE: u8 = 0xdd;
+
+const ALLOCATED_MAGIC: u64 = 0xa110_ca7e_d0d0_beef;
+const FREED_MAGIC: u64 = 0xf4ee_d0d0_dead_beef;
+
+/// Written directly in front of the leading guard bytes of an allocation.
+#[repr(C)]
+struct AllocationHeader {
+    magic: u64,
+    size: usize,
+    align: usize,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<AllocationHeader>();
+
+/// Number of problems reported so far.
+static PROBLEMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of heap problems which were reported since boot.
+pub fn problems() -> usize {
+    PROBLEMS.load(Ordering::SeqCst)
+}
+
+/// Wrapper around a global allocator which helps finding heap corruption.
+///
+/// Every allocation is surrounded by guard bytes which are checked when it is
+/// freed, fresh memory is filled with `UNINIT_BYTE` and freed memory with
+/// `POISON_BYTE`. A header in front of each allocation is used to detect double
+/// frees and layout mismatches. Problems are reported over the serial port.
+///
+/// Double frees are only detected as long as the freed block has not been
+/// handed out again by the underlying allocator.
+pub struct DebugAllocator<A: 'static> {
+    inner: &'static A,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        DebugAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (inner_layout, prefix) = match padded_layout(&layout) {
+            Some(padded) => padded,
+            None => return ptr::null_mut(),
+        };
+
+        let base = unsafe { self.inner.alloc(inner_layout) };
+        if base.is_null() {
+            return base;
+        }
+
+        let user = base as usize + prefix;
+        unsafe {
+            header(user).write(AllocationHeader {
+                magic: ALLOCATED_MAGIC,
+                size: layout.size(),
+                align: layout.align(),
+            });
+            fill(user - GUARD_SIZE, GUARD_SIZE, GUARD_BYTE);
+            fill(user, layout.size(), UNINIT_BYTE);
+            fill(user + layout.size(), GUARD_SIZE, GUARD_BYTE);
+        }
+
+        user as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let user = ptr as usize;
+        let header = unsafe { &mut *header(user) };
+
+        match header.magic {
+            ALLOCATED_MAGIC => {}
+            FREED_MAGIC => {
+                PROBLEMS.fetch_add(1, Ordering::SeqCst);
+                serial_println!("HEAP: double free of {:#x} (size {})", user, layout.size());
+                return;
+            }
+            _ => {
+                PROBLEMS.fetch_add(1, Ordering::SeqCst);
+                serial_println!("HEAP: free of {:#x} which was not allocated or has a corrupted header", user);
+                return;
+            }
+        }
+
+        if header.size != layout.size() || header.align != layout.align() {
+            PROBLEMS.fetch_add(1, Ordering::SeqCst);
+            serial_println!(
+                "HEAP: layout mismatch freeing {:#x}: allocated with size {} align {}, freed with size {} align {}",
+                user, header.size, header.align, layout.size(), layout.align()
+            );
+        }
+
+        // free with the recorded layout, so that the underlying allocator stays consistent
+        let layout = Layout::from_size_align(header.size, header.align).unwrap();
+        let (inner_layout, prefix) = padded_layout(&layout).unwrap();
+
+        unsafe {
+            check_guard(user, user - GUARD_SIZE, "underflow");
+            check_guard(user, user + layout.size(), "overflow");
+
+            fill(user - GUARD_SIZE, GUARD_SIZE + layout.size() + GUARD_SIZE, POISON_BYTE);
+        }
+        header.magic = FREED_MAGIC;
+
+        unsafe {
+            self.inner.dealloc((user - prefix) as *mut u8, inner_layout);
+        }
+    }
+}
+
+/// Computes the layout requested from the underlying allocator for the given
+/// layout.
+///
+/// Returns the padded layout and the offset of the user data within it.
+fn padded_layout(layout: &Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(mem::align_of::<AllocationHeader>());
+    let prefix = super::align_up(METADATA_RESERVE + HEADER_SIZE + GUARD_SIZE, align);
+    let size = prefix.checked_add(layout.size())?.checked_add(GUARD_SIZE)?;
+
+    Some((Layout::from_size_align(size, align).ok()?, prefix))
+}
+
+fn header(user: usize) -> *mut AllocationHeader {
+    (user - GUARD_SIZE - HEADER_SIZE) as *mut AllocationHeader
+}
+
+unsafe fn fill(addr: usize, size: usize, byte: u8) {
+    unsafe {
+        ptr::write_bytes(addr as *mut u8, byte, size);
+    }
+}
+
+/// Reports the first modified byte of the guard at `guard` belonging to the
+/// allocation at `user`.
+unsafe fn check_guard(user: usize, guard: usize, kind: &str) {
+    let bytes = unsafe { slice::from_raw_parts(guard as *const u8, GUARD_SIZE) };
+
+    if let Some(offset) = bytes.iter().position(|&byte| byte != GUARD_BYTE) {
+        PROBLEMS.fetch_add(1, Ordering::SeqCst);
+        serial_println!(
+            "HEAP: buffer {} of allocation {:#x} detected at {:#x}",
+            kind, user, guard + offset
+        );
+    }
+}
@@ -1,9 +1,9 @@
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr::NonNull};
-use crate::allocator::Locked;
+use crate::allocator::{HeapStats, Locked, Stats, Usage};
 use super::linked_list::LinkedListAllocator;
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 struct RegionNode {
     next: Option<&'static mut RegionNode>,
@@ -12,6 +12,7 @@ struct RegionNode {
 pub struct FixedSizeBlockAllocator {
     block_heads: [Option<&'static mut RegionNode>; BLOCK_SIZES.len()],
     fallback_allocator: LinkedListAllocator,
+    usage: Usage,
 }
 
 impl FixedSizeBlockAllocator {
@@ -22,14 +23,17 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             block_heads: [EMPTY_NODE; BLOCK_SIZES.len()],
             fallback_allocator: LinkedListAllocator::new(),
+            usage: Usage::new(),
         }
     }
 
     /// Initialize the allocator with the given heap bounds.
     ///
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
+    /// # Safety
+    ///
+    /// The memory from `heap_start` to `heap_start + heap_size` must be mapped
+    /// writable and unused for the lifetime of the allocator. This method must
+    /// be called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         unsafe {
             self.fallback_allocator.init(heap_start, heap_size);
@@ -37,6 +41,12 @@ impl FixedSizeBlockAllocator {
     }
 
     fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = self.allocate_block(layout)?;
+        self.usage.record_allocation(layout.size());
+        Some(ptr)
+    }
+
+    fn allocate_block(&mut self, layout: Layout) -> Option<NonNull<u8>> {
         match block_index(&layout) {
             Some(block_i) => {
                 match self.block_heads[block_i].take() {
@@ -51,15 +61,32 @@ impl FixedSizeBlockAllocator {
                         let block_align = block_size;
                         let layout = Layout::from_size_align(block_size, block_align).unwrap();
 
-                        self.fallback_allocator.allocate(layout)
+                        self.fallback_allocate(layout)
                     }
                 }
             }
-            None => self.fallback_allocator.allocate(layout),
+            None => self.fallback_allocate(layout),
         }
     }
 
+    /// Allocates from the fallback allocator, growing the heap if it has no
+    /// region left which fits the layout.
+    fn fallback_allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        if let Some(ptr) = self.fallback_allocator.allocate(layout) {
+            return Some(ptr);
+        }
+
+        let (region_start, region_size) = super::grow_heap(layout.size().saturating_add(layout.align()))?;
+        unsafe {
+            self.fallback_allocator.extend(region_start, region_size);
+        }
+
+        self.fallback_allocator.allocate(layout)
+    }
+
     fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.usage.record_deallocation(layout.size());
+
         match block_index(&layout) {
             Some(block_i) => {
                 let new_node = RegionNode {
@@ -85,20 +112,52 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapStats for FixedSizeBlockAllocator {
+    fn stats(&self) -> Stats {
+        let fallback_stats = self.fallback_allocator.stats();
+        let mut largest_free_block = fallback_stats.largest_free_block;
+        let mut block_lists = [0; BLOCK_SIZES.len()];
+
+        for (block_i, head) in self.block_heads.iter().enumerate() {
+            let mut current = head;
+            while let Some(node) = current {
+                block_lists[block_i] += 1;
+                current = &node.next;
+            }
+
+            if block_lists[block_i] > 0 {
+                largest_free_block = largest_free_block.max(BLOCK_SIZES[block_i]);
+            }
+        }
+
+        let mut stats = Stats::new(&self.usage, largest_free_block);
+        for length in block_lists {
+            stats.push_free_list(length);
+        }
+        stats
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         use core::ptr;
 
-        match self.lock().allocate(layout) {
+        match self.with_lock(|allocator| allocator.allocate(layout)) {
             Some(ptr) => ptr.as_ptr(),
             None => ptr::null_mut(),
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        unsafe {
-            self.lock().deallocate(NonNull::new_unchecked(ptr), layout);
-        }
+        self.with_lock(|allocator| unsafe {
+            allocator.deallocate(NonNull::new_unchecked(ptr), layout);
+        });
     }
 }
 
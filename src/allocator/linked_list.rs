@@ -1,6 +1,6 @@
 use core::{mem, ptr::NonNull};
 use alloc::alloc::{GlobalAlloc, Layout};
-use super::Locked;
+use super::{HeapStats, Locked, Stats, Usage};
 
 struct RegionNode {
     size: usize,
@@ -23,6 +23,7 @@ impl RegionNode {
 
 pub struct LinkedListAllocator {
     head: RegionNode,
+    usage: Usage,
 }
 
 impl LinkedListAllocator {
@@ -30,34 +31,79 @@ impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: RegionNode::new(0),
+            usage: Usage::new(),
         }
     }
 
     /// Initialize the allocator with the given heap bounds.
     ///
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
+    /// # Safety
+    ///
+    /// The memory from `heap_start` to `heap_start + heap_size` must be mapped
+    /// writable and unused for the lifetime of the allocator. This method must
+    /// be called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         unsafe {
             self.add_free_region(heap_start, heap_size);
         }
     }
 
-    /// Adds the given memory region to the front of the list.
+    /// Adds a newly mapped memory region to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The memory from `addr` to `addr + size` must be mapped writable and
+    /// unused. It belongs to the allocator from then on and must stay mapped
+    /// for the lifetime of the allocator.
+    pub unsafe fn extend(&mut self, addr: usize, size: usize) {
+        unsafe {
+            self.add_free_region(addr, size);
+        }
+    }
+
+    /// Inserts the given memory region into the list, which is kept sorted by
+    /// address, and merges it with its neighbours if they are adjacent.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(super::align_up(addr, mem::align_of::<RegionNode>()), addr);
         assert!(size >= mem::size_of::<RegionNode>());
 
-        // TODO: insert in an ordered manner
+        let head_addr = self.head.start_addr();
+
+        // find the last region which starts before the new one
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|next| next.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let is_head = current.start_addr() == head_addr;
+        assert!(is_head || current.end_addr() <= addr, "Freed region overlaps a free region");
 
         let mut node = RegionNode::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
+
+        // merge the following region into the new one
+        if let Some(next) = node.next.take() {
+            assert!(addr + size <= next.start_addr(), "Freed region overlaps a free region");
+
+            if addr + size == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // merge the new region into the preceding one
+        if !is_head && current.end_addr() == addr {
+            current.size += node.size;
+            current.next = node.next;
+            return;
+        }
 
         let node_ptr = addr as *mut RegionNode;
         unsafe {
             node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr)
+            current.next = Some(&mut *node_ptr)
         }
     }
 
@@ -69,7 +115,7 @@ impl LinkedListAllocator {
         let mut current = &mut self.head;
 
         while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
                 let next = region.next.take();
                 let ret = Some((current.next.take().unwrap(), alloc_start));
                 current.next = next;
@@ -95,14 +141,22 @@ impl LinkedListAllocator {
                 }
             }
 
+            self.usage.record_allocation(layout.size());
             NonNull::new(alloc_start as *mut u8)
         } else {
             None
         }
     }
 
+    /// Returns the memory of an allocation to the free list.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` for the same `layout` and
+    /// must not be used afterwards.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
         let (size, _) = LinkedListAllocator::size_align(layout);
+        self.usage.record_deallocation(layout.size());
 
         unsafe {
             self.add_free_region(ptr.addr().get(), size);
@@ -145,19 +199,43 @@ impl LinkedListAllocator {
     }
 }
 
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapStats for LinkedListAllocator {
+    fn stats(&self) -> Stats {
+        let mut regions = 0;
+        let mut largest_region = 0;
+
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            regions += 1;
+            largest_region = largest_region.max(region.size);
+            current = &region.next;
+        }
+
+        let mut stats = Stats::new(&self.usage, largest_region);
+        stats.push_free_list(regions);
+        stats
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         use core::ptr;
 
-        match self.lock().allocate(layout) {
+        match self.with_lock(|allocator| allocator.allocate(layout)) {
             Some(ptr) => ptr.as_ptr(),
             None => ptr::null_mut(),
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        unsafe {
-            self.lock().deallocate(NonNull::new_unchecked(ptr), layout);
-        }
+        self.with_lock(|allocator| unsafe {
+            allocator.deallocate(NonNull::new_unchecked(ptr), layout);
+        });
     }
 }
\ No newline at end of file
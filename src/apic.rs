@@ -0,0 +1,306 @@
+pub mod io;
+pub mod local;
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{self, AcpiError};
+use crate::acpi::madt::{Madt, Polarity, TriggerMode};
+use crate::interrupts::{self, InterruptIndex, IRQ_COUNT, PIC_1_OFFSET, SPURIOUS_INTERRUPT};
+use crate::{memory, time};
+use io::{IoApic, RedirectionEntry};
+use local::LocalApic;
+
+/// Size of the register block of the local APIC and of each I/O APIC.
+const REGISTERS_SIZE: u64 = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// The CPU has no local APIC.
+    Unsupported,
+    /// The MADT could not be read.
+    Acpi(AcpiError),
+    /// The MADT does not describe any I/O APIC.
+    NoIoApic,
+    /// The APIC registers could not be mapped.
+    MappingFailed,
+    /// `init` has not been called successfully.
+    NotEnabled,
+    /// No I/O APIC handles the given global system interrupt.
+    InvalidGsi(u32),
+    /// The ISA IRQ is not routed to an I/O APIC.
+    InvalidIrq(u8),
+}
+
+impl From<AcpiError> for ApicError {
+    fn from(error: AcpiError) -> Self {
+        ApicError::Acpi(error)
+    }
+}
+
+/// Virtual address of the local APIC registers, zero while the PICs are in use.
+static LOCAL_APIC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Local APIC timer ticks per millisecond, measured against the PIT.
+static TIMER_TICKS_PER_MS: AtomicU32 = AtomicU32::new(0);
+
+static ROUTING: Mutex<Option<IrqRouting>> = Mutex::new(None);
+
+/// I/O APIC input pin to which an ISA IRQ is connected.
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    io_apic: usize,
+    pin: u8,
+    /// Polarity given by an interrupt source override.
+    polarity: Polarity,
+}
+
+struct IrqRouting {
+    io_apics: Vec<IoApic>,
+    routes: [Option<Route>; IRQ_COUNT as usize],
+    /// APIC ID of the local APIC receiving all interrupts.
+    destination: u8,
+    /// APIC IDs of the enabled processors listed in the MADT.
+    processors: Vec<u8>,
+}
+
+/// Switches interrupt delivery from the 8259 PICs to the local APIC and the
+/// I/O APICs described by the MADT.
+///
+/// ISA IRQs keep their vectors and mask state, so registered IRQ handlers keep
+/// working. The PIT is replaced by the local APIC timer, which keeps driving the
+/// timer interrupt at `time::frequency()`. Requires the heap and `memory::init`.
+pub fn init() -> Result<(), ApicError> {
+    if !is_supported() {
+        return Err(ApicError::Unsupported);
+    }
+
+    let madt = acpi::madt::parse()?;
+    if madt.io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let local_apic = unsafe { LocalApic::new(map_registers(madt.local_apic_address)?) };
+
+    let mut io_apics = Vec::with_capacity(madt.io_apics.len());
+    for io_apic in &madt.io_apics {
+        let base = map_registers(u64::from(io_apic.address))?;
+        io_apics.push(unsafe { IoApic::new(io_apic.id, base, io_apic.gsi_base) });
+    }
+
+    without_interrupts(|| {
+        let unmasked = interrupts::disable_pics();
+
+        local_apic.enable(SPURIOUS_INTERRUPT);
+        for nmi in &madt.local_apic_nmis {
+            if nmi.processor_id == 0xff || is_local_processor(&madt, nmi.processor_id, local_apic.id()) {
+                local_apic.set_nmi(nmi);
+            }
+        }
+
+        let mut routing = route_isa_irqs(&madt, io_apics, local_apic.id(), unmasked);
+        routing.processors = madt.processors
+            .iter()
+            .filter(|processor| processor.enabled)
+            .map(|processor| processor.apic_id)
+            .collect();
+        *ROUTING.lock() = Some(routing);
+        LOCAL_APIC_BASE.store(local_apic.base().as_u64(), Ordering::SeqCst);
+
+        let ticks_per_ms = local_apic.calibrate_timer(time::pit::wait_ms);
+        TIMER_TICKS_PER_MS.store(ticks_per_ms, Ordering::Relaxed);
+        set_timer_frequency(time::frequency());
+    });
+
+    Ok(())
+}
+
+/// Returns whether interrupts are delivered through the APICs instead of the PICs.
+pub fn is_enabled() -> bool {
+    LOCAL_APIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Returns the APIC ID of the local APIC, if `init` succeeded.
+pub fn local_apic_id() -> Option<u8> {
+    let base = LOCAL_APIC_BASE.load(Ordering::Relaxed);
+    (base != 0).then(|| unsafe { LocalApic::new(VirtAddr::new(base)) }.id())
+}
+
+/// Returns whether the MADT lists an enabled processor with the given APIC ID,
+/// which interrupts can be delivered to. Always false until `init` succeeded.
+pub fn is_processor(apic_id: u8) -> bool {
+    without_interrupts(|| {
+        ROUTING.lock().as_ref().is_some_and(|routing| routing.processors.contains(&apic_id))
+    })
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+///
+/// Must only be called after `init` succeeded.
+pub fn end_of_interrupt() {
+    let base = VirtAddr::new(LOCAL_APIC_BASE.load(Ordering::Relaxed));
+    unsafe { LocalApic::new(base) }.end_of_interrupt();
+}
+
+/// Restarts the local APIC timer to fire the timer interrupt at the given
+/// frequency.
+///
+/// Must only be called after `init` succeeded, use `time::set_frequency` instead.
+pub(crate) fn set_timer_frequency(frequency_hz: u32) {
+    let ticks_per_ms = TIMER_TICKS_PER_MS.load(Ordering::Relaxed);
+    let initial_count = (u64::from(ticks_per_ms) * 1000 / u64::from(frequency_hz)).clamp(1, u64::from(u32::MAX));
+
+    let base = VirtAddr::new(LOCAL_APIC_BASE.load(Ordering::Relaxed));
+    unsafe { LocalApic::new(base) }.start_periodic_timer(InterruptIndex::Timer as u8, initial_count as u32);
+}
+
+/// Masks or unmasks the I/O APIC pin to which the given ISA IRQ is routed.
+///
+/// IRQs which are not routed, like the PIT which is replaced by the local
+/// APIC timer, stay masked.
+pub fn set_irq_masked(irq: u8, masked: bool) {
+    without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let Some(routing) = routing.as_mut() else {
+            return;
+        };
+
+        if let Some(Some(route)) = routing.routes.get(usize::from(irq)) {
+            routing.io_apics[route.io_apic].set_masked(route.pin, masked);
+        }
+    });
+}
+
+/// Returns whether the I/O APIC pin to which the given ISA IRQ is routed is
+/// masked, which IRQs that are not routed always are.
+pub fn is_irq_masked(irq: u8) -> bool {
+    without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let Some(routing) = routing.as_mut() else {
+            return true;
+        };
+
+        match routing.routes.get(usize::from(irq)) {
+            Some(Some(route)) => routing.io_apics[route.io_apic].is_masked(route.pin),
+            _ => true,
+        }
+    })
+}
+
+/// Switches the I/O APIC pin of an ISA IRQ, which a PCI device uses for its
+/// INTx interrupt, to level triggered.
+///
+/// PCI interrupts are active low, unless an interrupt source override of the
+/// MADT gives the polarity. The vector and the mask state are kept.
+pub fn set_pci_irq(irq: u8) -> Result<(), ApicError> {
+    without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let routing = routing.as_mut().ok_or(ApicError::NotEnabled)?;
+
+        let destination = routing.destination;
+        let route = routing.routes.get(usize::from(irq)).copied().flatten().ok_or(ApicError::InvalidIrq(irq))?;
+        let io_apic = &mut routing.io_apics[route.io_apic];
+
+        let masked = io_apic.is_masked(route.pin);
+        io_apic.set_entry(route.pin, RedirectionEntry {
+            vector: PIC_1_OFFSET + irq,
+            active_low: route.polarity != Polarity::ActiveHigh,
+            level_triggered: true,
+            masked,
+            destination,
+        });
+        Ok(())
+    })
+}
+
+/// Delivers the given global system interrupt to `vector` and unmasks it.
+///
+/// Used for interrupt sources other than ISA IRQs, which are routed by `init`.
+pub fn route_gsi(gsi: u32, vector: u8, active_low: bool, level_triggered: bool) -> Result<(), ApicError> {
+    without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let routing = routing.as_mut().ok_or(ApicError::NotEnabled)?;
+
+        let destination = routing.destination;
+        let (io_apic, pin) = routing
+            .io_apics
+            .iter_mut()
+            .find_map(|io_apic| {
+                let pin = io_apic.pin_for(gsi)?;
+                Some((io_apic, pin))
+            })
+            .ok_or(ApicError::InvalidGsi(gsi))?;
+
+        io_apic.set_entry(pin, RedirectionEntry {
+            vector,
+            active_low,
+            level_triggered,
+            masked: false,
+            destination,
+        });
+        Ok(())
+    })
+}
+
+fn is_supported() -> bool {
+    const CPUID_EDX_APIC: u32 = 1 << 9;
+
+    let cpuid = core::arch::x86_64::__cpuid(1);
+    cpuid.edx & CPUID_EDX_APIC != 0
+}
+
+fn is_local_processor(madt: &Madt, processor_id: u8, apic_id: u8) -> bool {
+    madt.processors
+        .iter()
+        .any(|processor| processor.processor_id == processor_id && processor.apic_id == apic_id)
+}
+
+fn map_registers(address: u64) -> Result<VirtAddr, ApicError> {
+    memory::map_mmio(PhysAddr::new(address), REGISTERS_SIZE).map_err(|_| ApicError::MappingFailed)
+}
+
+/// Programs a redirection entry for every ISA IRQ, applying the interrupt
+/// source overrides of the MADT.
+///
+/// `unmasked` has a bit set for every IRQ which was unmasked at the PICs.
+fn route_isa_irqs(madt: &Madt, mut io_apics: Vec<IoApic>, destination: u8, unmasked: u16) -> IrqRouting {
+    // the PIT is replaced by the local APIC timer and the cascade line has no device
+    const UNROUTED: [u8; 2] = [0, 2];
+
+    let mut routes = [None; IRQ_COUNT as usize];
+
+    for irq in (0..IRQ_COUNT).filter(|irq| !UNROUTED.contains(irq)) {
+        let (gsi, polarity, trigger_mode) = madt
+            .interrupt_overrides
+            .iter()
+            .find(|entry| entry.bus == 0 && entry.source_irq == irq)
+            .map(|entry| (entry.gsi, entry.polarity, entry.trigger_mode))
+            .unwrap_or((u32::from(irq), Polarity::ConformsToBus, TriggerMode::ConformsToBus));
+
+        let Some((index, pin)) = io_apics
+            .iter()
+            .enumerate()
+            .find_map(|(index, io_apic)| Some((index, io_apic.pin_for(gsi)?)))
+        else {
+            continue;
+        };
+
+        // ISA interrupts are active high and edge triggered unless overridden
+        io_apics[index].set_entry(pin, RedirectionEntry {
+            vector: PIC_1_OFFSET + irq,
+            active_low: polarity == Polarity::ActiveLow,
+            level_triggered: trigger_mode == TriggerMode::Level,
+            masked: unmasked & (1 << irq) == 0,
+            destination,
+        });
+        routes[usize::from(irq)] = Some(Route { io_apic: index, pin, polarity });
+    }
+
+    IrqRouting {
+        io_apics,
+        routes,
+        destination,
+        processors: Vec::new(),
+    }
+}
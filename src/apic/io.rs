@@ -0,0 +1,128 @@
+use core::ptr;
+use x86_64::VirtAddr;
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+
+/// Routing of a single I/O APIC input pin to a local APIC.
+///
+/// Interrupts are always delivered in fixed, physical destination mode.
+#[derive(Debug, Clone, Copy)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+    pub masked: bool,
+    /// APIC ID of the local APIC which receives the interrupt.
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+    fn to_bits(self) -> u64 {
+        let mut bits = u64::from(self.vector) | u64::from(self.destination) << 56;
+
+        if self.active_low {
+            bits |= ENTRY_ACTIVE_LOW;
+        }
+        if self.level_triggered {
+            bits |= ENTRY_LEVEL_TRIGGERED;
+        }
+        if self.masked {
+            bits |= ENTRY_MASKED;
+        }
+
+        bits
+    }
+}
+
+/// Memory mapped registers of an I/O APIC.
+#[derive(Debug)]
+pub struct IoApic {
+    id: u8,
+    base: VirtAddr,
+    gsi_base: u32,
+    pin_count: u8,
+}
+
+impl IoApic {
+    /// Creates a handle to the I/O APIC whose registers are mapped at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must map the registers of the I/O APIC as uncacheable memory for
+    /// as long as the handle is used. No other handle may access the same I/O
+    /// APIC, since selecting a register and accessing it are separate writes.
+    pub unsafe fn new(id: u8, base: VirtAddr, gsi_base: u32) -> Self {
+        let mut io_apic = IoApic {
+            id,
+            base,
+            gsi_base,
+            pin_count: 0,
+        };
+
+        io_apic.pin_count = ((io_apic.read(REG_VERSION) >> 16) & 0xff) as u8 + 1;
+        io_apic
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + REG_SELECT).as_mut_ptr::<u32>(), register);
+            ptr::read_volatile((self.base + REG_WINDOW).as_ptr::<u32>())
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + REG_SELECT).as_mut_ptr::<u32>(), register);
+            ptr::write_volatile((self.base + REG_WINDOW).as_mut_ptr::<u32>(), value);
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns the input pin receiving the given global system interrupt, if it
+    /// belongs to this I/O APIC.
+    pub fn pin_for(&self, gsi: u32) -> Option<u8> {
+        let pin = gsi.checked_sub(self.gsi_base)?;
+        (pin < u32::from(self.pin_count)).then_some(pin as u8)
+    }
+
+    pub fn pin_count(&self) -> u8 {
+        self.pin_count
+    }
+
+    pub fn set_entry(&mut self, pin: u8, entry: RedirectionEntry) {
+        let register = REG_REDIRECTION_TABLE + u32::from(pin) * 2;
+        let bits = entry.to_bits();
+
+        // keep the entry masked while it is only partially written
+        self.write(register, ENTRY_MASKED as u32);
+        self.write(register + 1, (bits >> 32) as u32);
+        self.write(register, bits as u32);
+    }
+
+    pub fn is_masked(&mut self, pin: u8) -> bool {
+        let register = REG_REDIRECTION_TABLE + u32::from(pin) * 2;
+        u64::from(self.read(register)) & ENTRY_MASKED != 0
+    }
+
+    pub fn set_masked(&mut self, pin: u8, masked: bool) {
+        let register = REG_REDIRECTION_TABLE + u32::from(pin) * 2;
+        let low = self.read(register);
+
+        if masked {
+            self.write(register, low | ENTRY_MASKED as u32);
+        } else {
+            self.write(register, low & !(ENTRY_MASKED as u32));
+        }
+    }
+}
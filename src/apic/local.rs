@@ -0,0 +1,125 @@
+use core::ptr;
+use x86_64::VirtAddr;
+use crate::acpi::madt::{LocalApicNmi, Polarity, TriggerMode};
+
+const REG_ID: usize = 0x20;
+const REG_TASK_PRIORITY: usize = 0x80;
+const REG_EOI: usize = 0xb0;
+const REG_SPURIOUS_VECTOR: usize = 0xf0;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_LVT_LINT0: usize = 0x350;
+const REG_LVT_LINT1: usize = 0x360;
+const REG_TIMER_INITIAL_COUNT: usize = 0x380;
+const REG_TIMER_CURRENT_COUNT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL_TRIGGERED: u32 = 1 << 15;
+
+/// Divides the bus clock by 16 for the timer.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// Memory mapped registers of the local APIC of the current CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    /// Creates a handle to the local APIC whose registers are mapped at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must map the local APIC registers as uncacheable memory for as
+    /// long as the handle is used.
+    pub unsafe fn new(base: VirtAddr) -> Self {
+        LocalApic { base }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + register).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + register).as_mut_ptr::<u32>(), value) }
+    }
+
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(REG_ID) >> 24) as u8
+    }
+
+    /// Enables the local APIC, delivering spurious interrupts to the given vector.
+    pub fn enable(&self, spurious_vector: u8) {
+        use x86_64::registers::model_specific::Msr;
+
+        unsafe {
+            let mut apic_base = Msr::new(IA32_APIC_BASE_MSR);
+            let value = apic_base.read();
+            apic_base.write(value | APIC_GLOBAL_ENABLE);
+        }
+
+        // accept interrupts of every priority
+        self.write(REG_TASK_PRIORITY, 0);
+        self.write(REG_SPURIOUS_VECTOR, SOFTWARE_ENABLE | u32::from(spurious_vector));
+    }
+
+    /// Connects the local interrupt pin described by the MADT to the NMI.
+    pub fn set_nmi(&self, nmi: &LocalApicNmi) {
+        let register = match nmi.lint {
+            0 => REG_LVT_LINT0,
+            _ => REG_LVT_LINT1,
+        };
+
+        let mut value = LVT_DELIVERY_NMI;
+        if nmi.polarity == Polarity::ActiveLow {
+            value |= LVT_ACTIVE_LOW;
+        }
+        if nmi.trigger_mode == TriggerMode::Level {
+            value |= LVT_LEVEL_TRIGGERED;
+        }
+
+        self.write(register, value);
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(REG_EOI, 0);
+    }
+
+    /// Measures how many timer ticks pass during one millisecond.
+    ///
+    /// `wait_ms` has to wait for the given number of milliseconds without relying
+    /// on interrupts.
+    pub fn calibrate_timer(&self, wait_ms: impl FnOnce(u32)) -> u32 {
+        const CALIBRATION_MS: u32 = 10;
+
+        self.write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(REG_LVT_TIMER, LVT_MASKED);
+        self.write(REG_TIMER_INITIAL_COUNT, u32::MAX);
+
+        wait_ms(CALIBRATION_MS);
+
+        let elapsed = u32::MAX - self.read(REG_TIMER_CURRENT_COUNT);
+        self.write(REG_TIMER_INITIAL_COUNT, 0);
+
+        elapsed / CALIBRATION_MS
+    }
+
+    /// Starts the timer in periodic mode, firing `vector` every `initial_count`
+    /// ticks.
+    pub fn start_periodic_timer(&self, vector: u8, initial_count: u32) {
+        self.write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | u32::from(vector));
+        self.write(REG_TIMER_INITIAL_COUNT, initial_count);
+    }
+}
@@ -0,0 +1,55 @@
+pub mod ata;
+pub mod cache;
+pub mod virtio;
+
+/// Size of a sector of the disks supported by the drivers.
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches beyond the last block of the device.
+    OutOfRange,
+    /// The buffer length is not a multiple of the block size.
+    InvalidBufferSize(usize),
+    /// The device did not respond in time.
+    Timeout,
+    /// The device reported an error, with a device specific error code.
+    Device(u8),
+    /// The device can not be written to.
+    ReadOnly,
+}
+
+/// Storage device which is read and written in fixed size blocks.
+pub trait BlockDevice: Send {
+    /// Returns the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Returns the number of blocks of the device.
+    fn block_count(&self) -> u64;
+
+    /// Reads consecutive blocks starting at `start` into `buffer`, whose length
+    /// has to be a multiple of the block size.
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buffer` to consecutive blocks starting at `start`.
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes sure that all written blocks reached persistent storage.
+    fn flush(&mut self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+/// Returns the number of blocks in `buffer`, checking that they fit the device.
+pub fn check_request<D: BlockDevice + ?Sized>(device: &D, start: u64, buffer_len: usize) -> Result<u64, BlockError> {
+    let block_size = device.block_size();
+    if !buffer_len.is_multiple_of(block_size) {
+        return Err(BlockError::InvalidBufferSize(buffer_len));
+    }
+
+    let count = (buffer_len / block_size) as u64;
+    match start.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
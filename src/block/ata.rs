@@ -0,0 +1,389 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use spin::Mutex;
+use crate::time::{Duration, Instant};
+use super::{check_request, BlockDevice, BlockError, SECTOR_SIZE};
+
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_DEVICE_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+
+const CONTROL_DISABLE_INTERRUPTS: u8 = 1 << 1;
+const CONTROL_SOFTWARE_RESET: u8 = 1 << 2;
+
+const DRIVE_BASE: u8 = 0xa0;
+const DRIVE_LBA: u8 = 1 << 6;
+const DRIVE_SLAVE: u8 = 1 << 4;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_READ_SECTORS_EXT: u8 = 0x24;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_WRITE_SECTORS_EXT: u8 = 0x34;
+const COMMAND_FLUSH_CACHE: u8 = 0xe7;
+const COMMAND_FLUSH_CACHE_EXT: u8 = 0xea;
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// Maximum number of sectors transferred by a single command.
+const MAX_SECTORS_LBA28: u64 = 256;
+const MAX_SECTORS_LBA48: u64 = 65536;
+/// Sectors addressable without the 48-bit commands.
+const LBA28_LIMIT: u64 = 1 << 28;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Bounds waiting when the clock can't be relied on, every poll being a port
+/// read which takes about a microsecond.
+const MAX_POLLS: u32 = 1_000_000;
+
+/// Words of the IDENTIFY response.
+const IDENTIFY_MODEL: core::ops::Range<usize> = 27..47;
+const IDENTIFY_LBA28_SECTORS: usize = 60;
+const IDENTIFY_COMMAND_SETS: usize = 83;
+const IDENTIFY_LBA48_SECTORS: usize = 100;
+const COMMAND_SETS_LBA48: u16 = 1 << 10;
+
+/// I/O ports of the primary and secondary IDE channel.
+const CHANNELS: [Channel; 2] = [
+    Channel {
+        io_base: 0x1f0,
+        control_base: 0x3f6,
+    },
+    Channel {
+        io_base: 0x170,
+        control_base: 0x376,
+    },
+];
+
+/// Serializes access to each channel, which is shared by both of its drives.
+static CHANNEL_LOCKS: [Mutex<()>; 2] = [Mutex::new(()), Mutex::new(())];
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    io_base: u16,
+    control_base: u16,
+}
+
+impl Channel {
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.io_base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.io_base + register).write(value) }
+    }
+
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.control_base).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::new(self.control_base).write(value) }
+    }
+
+    /// Waits about 400 ns, which the drive needs to update its status.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    /// Selects a drive of the channel and waits until it accepts commands.
+    fn select(&self, drive: u8) -> Result<(), BlockError> {
+        self.write(REG_DRIVE, drive);
+        self.delay();
+        self.wait_not_busy().map(|_| ())
+    }
+
+    /// Resets both drives of the channel, which aborts a failed transfer that
+    /// would otherwise keep the drive waiting for data.
+    fn reset(&self) {
+        self.set_control(CONTROL_DISABLE_INTERRUPTS | CONTROL_SOFTWARE_RESET);
+        // the reset bit has to stay set for at least 5 us
+        for _ in 0..13 {
+            self.delay();
+        }
+        self.set_control(CONTROL_DISABLE_INTERRUPTS);
+        self.delay();
+
+        // there is nothing left to do if the drives never come back
+        let _ = self.wait_not_busy();
+    }
+
+    /// Resets the channel if `result` is an error.
+    fn reset_on_error<T>(&self, result: Result<T, BlockError>) -> Result<T, BlockError> {
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// Waits until the drive is not busy anymore, returning its status.
+    fn wait_not_busy(&self) -> Result<u8, BlockError> {
+        let deadline = Instant::now() + TIMEOUT;
+        // the clock may not advance with interrupts disabled
+        let max_polls = if interrupts::are_enabled() { u32::MAX } else { MAX_POLLS };
+
+        for _ in 0..max_polls {
+            let status = self.alternate_status();
+            if status & STATUS_BUSY == 0 {
+                return Ok(status);
+            }
+            if Instant::now() > deadline {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(BlockError::Timeout)
+    }
+
+    /// Waits until the drive is ready to transfer data.
+    fn wait_data_request(&self) -> Result<(), BlockError> {
+        let status = self.wait_not_busy()?;
+
+        if status & (STATUS_ERROR | STATUS_DEVICE_FAULT) != 0 {
+            return Err(BlockError::Device(self.read(REG_ERROR)));
+        }
+        if status & STATUS_DATA_REQUEST == 0 {
+            return Err(BlockError::Device(0));
+        }
+
+        Ok(())
+    }
+
+    fn read_sector(&self, buffer: &mut [u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+
+        for word in buffer.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buffer: &[u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+
+        for word in buffer.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+}
+
+/// ATA disk accessed with programmed I/O.
+#[derive(Debug)]
+pub struct AtaDrive {
+    channel: usize,
+    slave: bool,
+    model: String,
+    sectors: u64,
+    lba48: bool,
+}
+
+impl AtaDrive {
+    /// Identifies the drive on the given channel, returning `None` if there is
+    /// no ATA drive.
+    pub fn identify(channel: usize, slave: bool) -> Option<Self> {
+        let ports = CHANNELS.get(channel)?;
+        let _lock = CHANNEL_LOCKS[channel].lock();
+
+        // a floating bus reads as all ones
+        if ports.alternate_status() == 0xff {
+            return None;
+        }
+
+        ports.set_control(CONTROL_DISABLE_INTERRUPTS);
+        ports.write(REG_DRIVE, DRIVE_BASE | if slave { DRIVE_SLAVE } else { 0 });
+        ports.delay();
+
+        for register in [REG_SECTOR_COUNT, REG_LBA_LOW, REG_LBA_MID, REG_LBA_HIGH] {
+            ports.write(register, 0);
+        }
+        ports.write(REG_COMMAND, COMMAND_IDENTIFY);
+        ports.delay();
+
+        if ports.read(REG_STATUS) == 0 {
+            return None;
+        }
+        ports.wait_not_busy().ok()?;
+
+        // ATAPI and SATA devices abort IDENTIFY and leave a signature
+        if ports.read(REG_LBA_MID) != 0 || ports.read(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        ports.wait_data_request().ok()?;
+
+        let mut bytes = [0; SECTOR_SIZE];
+        ports.read_sector(&mut bytes);
+        let words: Vec<u16> = bytes.chunks_exact(2).map(|word| u16::from_le_bytes([word[0], word[1]])).collect();
+
+        // the model is space padded ASCII with the bytes of each word swapped
+        let model = words[IDENTIFY_MODEL]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(char::from)
+            .collect::<String>()
+            .trim()
+            .into();
+
+        let lba48 = words[IDENTIFY_COMMAND_SETS] & COMMAND_SETS_LBA48 != 0;
+        let sectors = if lba48 {
+            (0..4).map(|i| u64::from(words[IDENTIFY_LBA48_SECTORS + i]) << (16 * i)).sum()
+        } else {
+            u64::from(words[IDENTIFY_LBA28_SECTORS]) | u64::from(words[IDENTIFY_LBA28_SECTORS + 1]) << 16
+        };
+
+        Some(AtaDrive {
+            channel,
+            slave,
+            model,
+            sectors,
+            lba48,
+        })
+    }
+
+    /// Returns the model name reported by the drive.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Returns the capacity in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.sectors * SECTOR_SIZE as u64
+    }
+
+    pub fn supports_lba48(&self) -> bool {
+        self.lba48
+    }
+
+    /// Sends a read or write command for `count` sectors starting at `lba`.
+    fn start_transfer(&self, ports: &Channel, lba: u64, count: u64, write: bool) -> Result<(), BlockError> {
+        let drive = DRIVE_BASE | DRIVE_LBA | if self.slave { DRIVE_SLAVE } else { 0 };
+
+        // a count of zero transfers the maximum number of sectors
+        let command = if self.lba48 {
+            ports.select(drive)?;
+            ports.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+            ports.write(REG_LBA_LOW, (lba >> 24) as u8);
+            ports.write(REG_LBA_MID, (lba >> 32) as u8);
+            ports.write(REG_LBA_HIGH, (lba >> 40) as u8);
+            if write { COMMAND_WRITE_SECTORS_EXT } else { COMMAND_READ_SECTORS_EXT }
+        } else {
+            ports.select(drive | ((lba >> 24) & 0x0f) as u8)?;
+            if write { COMMAND_WRITE_SECTORS } else { COMMAND_READ_SECTORS }
+        };
+
+        ports.write(REG_SECTOR_COUNT, count as u8);
+        ports.write(REG_LBA_LOW, lba as u8);
+        ports.write(REG_LBA_MID, (lba >> 8) as u8);
+        ports.write(REG_LBA_HIGH, (lba >> 16) as u8);
+        ports.write(REG_COMMAND, command);
+        Ok(())
+    }
+
+    fn read_sectors(&self, ports: &Channel, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let sectors_per_command = self.max_sectors_per_command() as usize;
+
+        for (index, chunk) in buffer.chunks_mut(sectors_per_command * SECTOR_SIZE).enumerate() {
+            let lba = start + (index * sectors_per_command) as u64;
+            self.start_transfer(ports, lba, (chunk.len() / SECTOR_SIZE) as u64, false)?;
+
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                ports.delay();
+                ports.wait_data_request()?;
+                ports.read_sector(sector);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_sectors(&self, ports: &Channel, start: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        let sectors_per_command = self.max_sectors_per_command() as usize;
+
+        for (index, chunk) in buffer.chunks(sectors_per_command * SECTOR_SIZE).enumerate() {
+            let lba = start + (index * sectors_per_command) as u64;
+            self.start_transfer(ports, lba, (chunk.len() / SECTOR_SIZE) as u64, true)?;
+
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                ports.delay();
+                ports.wait_data_request()?;
+                ports.write_sector(sector);
+            }
+
+            let status = ports.wait_not_busy()?;
+            if status & (STATUS_ERROR | STATUS_DEVICE_FAULT) != 0 {
+                return Err(BlockError::Device(ports.read(REG_ERROR)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush_cache(&self, ports: &Channel) -> Result<(), BlockError> {
+        ports.select(DRIVE_BASE | if self.slave { DRIVE_SLAVE } else { 0 })?;
+        ports.write(REG_COMMAND, if self.lba48 { COMMAND_FLUSH_CACHE_EXT } else { COMMAND_FLUSH_CACHE });
+        ports.delay();
+
+        let status = ports.wait_not_busy()?;
+        if status & (STATUS_ERROR | STATUS_DEVICE_FAULT) != 0 {
+            return Err(BlockError::Device(ports.read(REG_ERROR)));
+        }
+
+        Ok(())
+    }
+
+    fn max_sectors_per_command(&self) -> u64 {
+        if self.lba48 { MAX_SECTORS_LBA48 } else { MAX_SECTORS_LBA28 }
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        if self.lba48 { self.sectors } else { self.sectors.min(LBA28_LIMIT) }
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+
+        let ports = &CHANNELS[self.channel];
+        let _lock = CHANNEL_LOCKS[self.channel].lock();
+        ports.reset_on_error(self.read_sectors(ports, start, buffer))
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+
+        let ports = &CHANNELS[self.channel];
+        let _lock = CHANNEL_LOCKS[self.channel].lock();
+        ports.reset_on_error(self.write_sectors(ports, start, buffer))
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        let ports = &CHANNELS[self.channel];
+        let _lock = CHANNEL_LOCKS[self.channel].lock();
+        ports.reset_on_error(self.flush_cache(ports))
+    }
+}
+
+/// Identifies the master and slave drives of both IDE channels.
+pub fn probe() -> Vec<AtaDrive> {
+    (0..CHANNELS.len())
+        .flat_map(|channel| [false, true].map(|slave| AtaDrive::identify(channel, slave)))
+        .flatten()
+        .collect()
+}
@@ -0,0 +1,285 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use super::{check_request, BlockDevice, BlockError};
+
+/// Blocks read beyond a sequential read by default.
+pub const DEFAULT_READ_AHEAD: u64 = 8;
+
+/// Counters describing how well the cache works.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Blocks read from the cache.
+    pub hits: u64,
+    /// Blocks which had to be read from the device.
+    pub misses: u64,
+    /// Blocks read from the device before they were requested.
+    pub read_ahead: u64,
+    /// Blocks removed to make room for others.
+    pub evictions: u64,
+    /// Dirty blocks written to the device.
+    pub write_backs: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// Position in the LRU order, larger values were used more recently.
+    stamp: u64,
+}
+
+/// Write-back cache of the blocks of a device in heap memory.
+///
+/// The least recently used block is evicted once the cache is full, writing
+/// it back first if it is dirty. Dirty blocks reach the device on eviction,
+/// `sync` or drop.
+#[derive(Debug)]
+pub struct BlockCache<D: BlockDevice> {
+    device: D,
+    capacity: usize,
+    read_ahead: u64,
+    entries: BTreeMap<u64, Entry>,
+    /// Cached blocks ordered by their last use.
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    /// Block following the previous read, to detect sequential reads.
+    next_sequential: Option<u64>,
+    stats: CacheStats,
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+    /// Creates a cache holding up to `capacity` blocks of `device`.
+    pub fn new(device: D, capacity: usize) -> Self {
+        assert!(capacity > 0, "block cache needs room for at least one block");
+
+        BlockCache {
+            device,
+            capacity,
+            read_ahead: DEFAULT_READ_AHEAD,
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            next_sequential: None,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of cached blocks.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sets the number of blocks read beyond a sequential read, 0 disables
+    /// read-ahead.
+    pub fn set_read_ahead(&mut self, blocks: u64) {
+        self.read_ahead = blocks;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Writes all dirty blocks to the device and flushes it.
+    pub fn sync(&mut self) -> Result<(), BlockError> {
+        let block_size = self.device.block_size();
+        let dirty: Vec<u64> = self.entries.iter().filter(|(_, entry)| entry.dirty).map(|(&block, _)| block).collect();
+
+        // write runs of consecutive blocks with a single request
+        let mut run = Vec::new();
+        let mut index = 0;
+        while index < dirty.len() {
+            let start = dirty[index];
+            let length = dirty[index..].iter().zip(start..).take_while(|&(&block, expected)| block == expected).count();
+
+            run.clear();
+            run.reserve(length * block_size);
+            for block in start..start + length as u64 {
+                run.extend_from_slice(&self.entries[&block].data);
+            }
+            self.device.write_blocks(start, &run)?;
+
+            for block in start..start + length as u64 {
+                self.entries.get_mut(&block).unwrap().dirty = false;
+            }
+            self.stats.write_backs += length as u64;
+            index += length;
+        }
+
+        self.device.flush()
+    }
+
+    /// Writes back the dirty blocks and empties the cache.
+    pub fn clear(&mut self) -> Result<(), BlockError> {
+        self.sync()?;
+        self.entries.clear();
+        self.lru.clear();
+        Ok(())
+    }
+
+    /// Marks `block` as the most recently used one.
+    fn touch(&mut self, block: u64) {
+        let Some(entry) = self.entries.get_mut(&block) else {
+            return;
+        };
+
+        self.clock += 1;
+        self.lru.remove(&entry.stamp);
+        self.lru.insert(self.clock, block);
+        entry.stamp = self.clock;
+    }
+
+    fn insert(&mut self, block: u64, data: Box<[u8]>, dirty: bool) -> Result<(), BlockError> {
+        if let Some(entry) = self.entries.get_mut(&block) {
+            entry.data = data;
+            entry.dirty |= dirty;
+            self.touch(block);
+            return Ok(());
+        }
+
+        while self.entries.len() >= self.capacity {
+            self.evict()?;
+        }
+
+        self.clock += 1;
+        self.lru.insert(self.clock, block);
+        self.entries.insert(block, Entry {
+            data,
+            dirty,
+            stamp: self.clock,
+        });
+
+        Ok(())
+    }
+
+    /// Removes the least recently used block, writing it back if it is dirty.
+    fn evict(&mut self) -> Result<(), BlockError> {
+        let Some((_, &block)) = self.lru.first_key_value() else {
+            return Ok(());
+        };
+
+        let entry = &self.entries[&block];
+        if entry.dirty {
+            self.device.write_blocks(block, &entry.data)?;
+            self.stats.write_backs += 1;
+        }
+
+        self.lru.pop_first();
+        self.entries.remove(&block);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    /// Reads consecutive uncached blocks starting at `block` from the device
+    /// and caches them, returning their data.
+    fn load(&mut self, block: u64, count: u64) -> Result<Vec<u8>, BlockError> {
+        let block_size = self.device.block_size();
+        let mut data = vec![0; count as usize * block_size];
+        self.device.read_blocks(block, &mut data)?;
+
+        for (index, chunk) in data.chunks(block_size).enumerate() {
+            self.insert(block + index as u64, chunk.into(), false)?;
+        }
+
+        Ok(data)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for BlockCache<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let count = check_request(self, start, buffer.len())?;
+        let block_size = self.device.block_size();
+        let end = start + count;
+        let sequential = self.next_sequential == Some(start);
+
+        let mut block = start;
+        while block < end {
+            let offset = (block - start) as usize * block_size;
+
+            if let Some(entry) = self.entries.get(&block) {
+                buffer[offset..offset + block_size].copy_from_slice(&entry.data);
+                self.touch(block);
+                self.stats.hits += 1;
+                block += 1;
+                continue;
+            }
+
+            let uncached = |from: u64, to: u64| (from..to).take_while(|block| !self.entries.contains_key(block)).count() as u64;
+            let requested = uncached(block, end);
+            let mut run = requested;
+            if sequential && block + run == end {
+                let limit = end.saturating_add(self.read_ahead).min(self.device.block_count());
+                run += uncached(end, limit);
+            }
+            // blocks loaded beyond the capacity would only evict each other
+            let run = run.min(self.capacity as u64);
+            let requested = requested.min(run);
+
+            let data = self.load(block, run)?;
+            let length = requested as usize * block_size;
+            buffer[offset..offset + length].copy_from_slice(&data[..length]);
+
+            self.stats.misses += requested;
+            self.stats.read_ahead += run - requested;
+            block += requested;
+        }
+
+        self.next_sequential = Some(end);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+
+        for (index, chunk) in buffer.chunks(self.device.block_size()).enumerate() {
+            let block = start + index as u64;
+
+            match self.entries.get_mut(&block) {
+                Some(entry) => {
+                    entry.data.copy_from_slice(chunk);
+                    entry.dirty = true;
+                    self.touch(block);
+                }
+                None => self.insert(block, chunk.into(), true)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.sync()
+    }
+}
+
+impl<D: BlockDevice> Drop for BlockCache<D> {
+    fn drop(&mut self) {
+        // errors can not be reported here, call `sync` to handle them
+        let _ = self.sync();
+    }
+}
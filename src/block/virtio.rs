@@ -0,0 +1,389 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::interrupts::{free_vector, register_irq, IrqError, IRQ_COUNT};
+use crate::pci::device::COMMAND_INTERRUPT_DISABLE;
+use crate::pci::msi::MsiX;
+use crate::pci::{self, PciDevice};
+use crate::time::{Duration, Instant};
+use crate::virtio::{self, Buffer, LegacyTransport, VirtioError, Virtqueue};
+use crate::{apic, memory};
+use super::{check_request, BlockDevice, BlockError, SECTOR_SIZE};
+
+const FEATURE_READ_ONLY: u32 = 1 << 5;
+const FEATURE_FLUSH: u32 = 1 << 9;
+
+/// Offset of the capacity in sectors in the device configuration.
+const CONFIG_CAPACITY: u16 = 0x00;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+/// Written into the status byte before submission, never used by the device.
+const STATUS_PENDING: u8 = 0xff;
+
+const REQUEST_QUEUE: u16 = 0;
+const MSIX_QUEUE_ENTRY: u16 = 0;
+/// Descriptors of a request: header, data and status.
+const DESCRIPTORS_PER_REQUEST: u16 = 3;
+
+const FRAME_SIZE: usize = 4096;
+/// Frames of the bounce buffer, which limits the size of a single request.
+const DATA_FRAMES: usize = 16;
+const DATA_SIZE: usize = DATA_FRAMES * FRAME_SIZE;
+/// The status byte follows the request header in the first frame.
+const STATUS_OFFSET: u64 = 16;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+struct RequestHeader {
+    request_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Part of a device which is accessed by the interrupt handler.
+#[derive(Debug)]
+struct Shared {
+    transport: LegacyTransport,
+    queue: Mutex<Virtqueue>,
+    /// Number of requests the device has finished.
+    completed: AtomicU64,
+    legacy_interrupts: bool,
+}
+
+impl Shared {
+    fn handle_interrupt(&self) {
+        // a legacy interrupt line may be shared with other devices
+        if self.legacy_interrupts && self.transport.read_isr() & virtio::ISR_QUEUE == 0 {
+            return;
+        }
+
+        self.collect_completed();
+    }
+
+    /// Counts the requests which the device has finished since the last call.
+    fn collect_completed(&self) {
+        let mut queue = self.queue.lock();
+        while queue.pop_used().is_some() {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Devices which have to be checked for finished requests on an interrupt.
+static DEVICES: Mutex<Vec<Arc<Shared>>> = Mutex::new(Vec::new());
+/// Bitmap of the legacy IRQs the interrupt handler is registered on.
+static LEGACY_IRQS: AtomicU16 = AtomicU16::new(0);
+
+fn handle_interrupt(_: u8) {
+    for device in DEVICES.lock().iter() {
+        device.handle_interrupt();
+    }
+}
+
+/// Block device using the legacy interface of virtio-pci.
+///
+/// One request is in flight at a time. Its data goes through a physically
+/// contiguous bounce buffer, and its completion is signalled by an MSI-X or
+/// legacy interrupt.
+#[derive(Debug)]
+pub struct VirtioBlk {
+    shared: Arc<Shared>,
+    /// The request header and status in the first frame, followed by the
+    /// bounce buffer.
+    dma: PhysFrameRange,
+    capacity: u64,
+    features: u32,
+    submitted: u64,
+    msix: Option<(MsiX, u8)>,
+    failed: bool,
+}
+
+impl VirtioBlk {
+    /// Initializes the device and its request queue.
+    ///
+    /// Requires the heap and `memory::init`. MSI-X is used if the APIC is enabled.
+    pub fn new(device: &PciDevice) -> Result<Self, VirtioError> {
+        let mut transport = LegacyTransport::new(device)?;
+        transport.add_status(virtio::STATUS_ACKNOWLEDGE | virtio::STATUS_DRIVER);
+
+        let features = transport.device_features() & (FEATURE_READ_ONLY | FEATURE_FLUSH);
+        transport.set_guest_features(features);
+
+        let result = Self::set_up(device, &mut transport, features);
+        if result.is_err() {
+            transport.add_status(virtio::STATUS_FAILED);
+        }
+        result
+    }
+
+    fn set_up(device: &PciDevice, transport: &mut LegacyTransport, features: u32) -> Result<Self, VirtioError> {
+        let queue_size = transport.queue_size(REQUEST_QUEUE);
+        if queue_size == 0 {
+            return Err(VirtioError::QueueUnavailable(REQUEST_QUEUE));
+        }
+        if queue_size < DESCRIPTORS_PER_REQUEST {
+            return Err(VirtioError::InvalidQueueSize(queue_size));
+        }
+
+        let queue = Virtqueue::new(queue_size)?;
+        let dma = memory::with_memory(|_, frame_allocator| frame_allocator.allocate_contiguous(1 + DATA_FRAMES))
+            .flatten()
+            .ok_or(VirtioError::OutOfMemory)?;
+
+        let msix = match enable_msix(device, transport) {
+            Ok(msix) => Some(msix),
+            Err(_) => {
+                device.set_command(device.command() & !COMMAND_INTERRUPT_DISABLE);
+                if let Err(error) = register_legacy_irq(device.interrupt_line) {
+                    memory::with_memory(|_, frame_allocator| unsafe { frame_allocator.deallocate_contiguous(dma) });
+                    return Err(error);
+                }
+                None
+            }
+        };
+
+        transport.set_queue(REQUEST_QUEUE, &queue);
+        let shared = Arc::new(Shared {
+            transport: *transport,
+            queue: Mutex::new(queue),
+            completed: AtomicU64::new(0),
+            legacy_interrupts: msix.is_none(),
+        });
+        without_interrupts(|| DEVICES.lock().push(shared.clone()));
+
+        let capacity = transport.read_config_u64(CONFIG_CAPACITY);
+        transport.add_status(virtio::STATUS_DRIVER_OK);
+
+        Ok(VirtioBlk {
+            shared,
+            dma,
+            capacity,
+            features,
+            submitted: 0,
+            msix,
+            failed: false,
+        })
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.features & FEATURE_READ_ONLY != 0
+    }
+
+    fn header(&self) -> PhysAddr {
+        self.dma.start.start_address()
+    }
+
+    fn data(&self) -> VirtAddr {
+        memory::phys_to_virt(self.header() + FRAME_SIZE as u64)
+    }
+
+    /// Submits a request for `length` bytes of the bounce buffer and waits for
+    /// its completion.
+    fn request(&mut self, request_type: u32, sector: u64, length: usize) -> Result<(), BlockError> {
+        if self.failed {
+            return Err(BlockError::Timeout);
+        }
+
+        let header = memory::phys_to_virt(self.header());
+        let status = (header + STATUS_OFFSET).as_mut_ptr::<u8>();
+        unsafe {
+            ptr::write_volatile(header.as_mut_ptr(), RequestHeader {
+                request_type,
+                reserved: 0,
+                sector,
+            });
+            ptr::write_volatile(status, STATUS_PENDING);
+        }
+
+        let header_buffer = Buffer {
+            address: self.header(),
+            length: size_of::<RequestHeader>() as u32,
+            device_writable: false,
+        };
+        let data_buffer = Buffer {
+            address: self.header() + FRAME_SIZE as u64,
+            length: length as u32,
+            device_writable: request_type == REQUEST_IN,
+        };
+        let status_buffer = Buffer {
+            address: self.header() + STATUS_OFFSET,
+            length: 1,
+            device_writable: true,
+        };
+        let with_data = [header_buffer, data_buffer, status_buffer];
+        let without_data = [header_buffer, status_buffer];
+        let buffers = if length == 0 { &without_data[..] } else { &with_data[..] };
+
+        without_interrupts(|| self.shared.queue.lock().add(buffers))
+            .expect("virtio-blk request queue is full");
+        self.submitted += 1;
+        self.shared.transport.notify(REQUEST_QUEUE);
+
+        self.wait(self.submitted)?;
+
+        match unsafe { ptr::read_volatile(status) } {
+            STATUS_OK => Ok(()),
+            status => Err(BlockError::Device(status)),
+        }
+    }
+
+    /// Sleeps until the device finished `count` requests.
+    ///
+    /// Polls the request queue instead if interrupts are disabled, which they
+    /// are again when this function returns.
+    fn wait(&mut self, count: u64) -> Result<(), BlockError> {
+        let deadline = Instant::now() + TIMEOUT;
+        let interrupts_enabled = interrupts::are_enabled();
+
+        let result = loop {
+            // check with interrupts disabled, so that the wake up can not be missed
+            interrupts::disable();
+            if self.shared.completed.load(Ordering::SeqCst) >= count {
+                break Ok(());
+            }
+            if Instant::now() > deadline {
+                // the device may still write into the buffers later
+                self.failed = true;
+                break Err(BlockError::Timeout);
+            }
+
+            if interrupts_enabled {
+                interrupts::enable_and_hlt();
+            } else {
+                self.shared.collect_completed();
+                core::hint::spin_loop();
+            }
+        };
+
+        if interrupts_enabled {
+            interrupts::enable();
+        }
+        result
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+
+        let mut sector = start;
+        for chunk in buffer.chunks_mut(DATA_SIZE) {
+            self.request(REQUEST_IN, sector, chunk.len())?;
+            unsafe { ptr::copy_nonoverlapping(self.data().as_ptr(), chunk.as_mut_ptr(), chunk.len()) };
+            sector += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+        if self.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+
+        let mut sector = start;
+        for chunk in buffer.chunks(DATA_SIZE) {
+            unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), self.data().as_mut_ptr(), chunk.len()) };
+            self.request(REQUEST_OUT, sector, chunk.len())?;
+            sector += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        if self.features & FEATURE_FLUSH == 0 {
+            return Ok(());
+        }
+
+        self.request(REQUEST_FLUSH, 0, 0)
+    }
+}
+
+impl Drop for VirtioBlk {
+    fn drop(&mut self) {
+        // stop the device from accessing the queue and the bounce buffer
+        self.shared.transport.reset();
+        without_interrupts(|| DEVICES.lock().retain(|device| !Arc::ptr_eq(device, &self.shared)));
+
+        if let Some((msix, vector)) = self.msix.take() {
+            msix.disable();
+            let _ = free_vector(vector);
+        }
+
+        memory::with_memory(|_, frame_allocator| unsafe { frame_allocator.deallocate_contiguous(self.dma) });
+    }
+}
+
+/// Routes the interrupt of the request queue to an MSI-X vector.
+fn enable_msix(device: &PciDevice, transport: &mut LegacyTransport) -> Result<(MsiX, u8), VirtioError> {
+    let msix = MsiX::new(device)?;
+
+    let vector = match msix.allocate_local(MSIX_QUEUE_ENTRY, handle_interrupt) {
+        Ok(vector) => vector,
+        Err(error) => {
+            msix.disable();
+            return Err(error.into());
+        }
+    };
+
+    if let Err(error) = transport.set_msix_vectors(virtio::NO_VECTOR, REQUEST_QUEUE, MSIX_QUEUE_ENTRY) {
+        msix.disable();
+        let _ = free_vector(vector);
+        return Err(error);
+    }
+
+    Ok((msix, vector))
+}
+
+/// Registers the interrupt handler on a legacy IRQ, once for all devices on it.
+///
+/// With the I/O APIC, the IRQ is switched to the level triggered PCI mode.
+fn register_legacy_irq(irq: u8) -> Result<(), VirtioError> {
+    if irq >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq).into());
+    }
+
+    let bit = 1 << irq;
+    if LEGACY_IRQS.fetch_or(bit, Ordering::SeqCst) & bit == 0 {
+        let result = if apic::is_enabled() {
+            apic::set_pci_irq(irq).map_err(VirtioError::from)
+        } else {
+            Ok(())
+        };
+
+        if let Err(error) = result.and_then(|()| register_irq(irq, handle_interrupt).map_err(VirtioError::from)) {
+            LEGACY_IRQS.fetch_and(!bit, Ordering::SeqCst);
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Initializes every virtio block device found by `pci::init`.
+pub fn probe() -> Vec<VirtioBlk> {
+    pci::find_by_id(virtio::VENDOR_ID, virtio::DEVICE_ID_BLOCK_LEGACY)
+        .iter()
+        .filter_map(|device| VirtioBlk::new(device).ok())
+        .collect()
+}
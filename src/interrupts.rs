@@ -1,30 +1,50 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+mod exceptions;
+mod irq;
+mod vectors;
+
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use spin::Mutex;
 use pic8259::ChainedPics;
 use lazy_static::lazy_static;
-use crate::{gdt, print, println};
+use x86_64::VirtAddr;
+use crate::{apic, time, timer};
+use crate::thread::{context::context_switch_entry, scheduler};
+
+pub use irq::{register_irq, is_irq_masked, mask_irq, unmask_irq, IrqError, IrqHandler, IRQ_COUNT, MAX_HANDLERS_PER_IRQ};
+pub use vectors::{
+    allocate_vector, free_vector, VectorError, VectorHandler, DYNAMIC_VECTOR_COUNT, FIRST_DYNAMIC_VECTOR,
+};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// Software interrupt used by `thread::yield_now` to enter the scheduler.
+pub const YIELD_INTERRUPT: u8 = PIC_2_OFFSET + 8;
+/// Interrupt raised by the HPET comparator at the deadline of a timer event.
+pub const TIMER_EVENT_INTERRUPT: u8 = YIELD_INTERRUPT + 1;
+/// Vector of spurious interrupts raised by the local APIC.
+pub const SPURIOUS_INTERRUPT: u8 = 0xff;
+
 static PICS: Mutex<ChainedPics> = Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut table = InterruptDescriptorTable::new();
 
-        table.breakpoint.set_handler_fn(handle_breakpoint_exception);
-        table.page_fault.set_handler_fn(handle_page_fault_exception);
+        exceptions::set_handlers(&mut table);
+        irq::set_handlers(&mut table);
+        vectors::set_handlers(&mut table);
+        table[usize::from(TIMER_EVENT_INTERRUPT)].set_handler_fn(timer_event_interrupt_handler);
+        table[usize::from(SPURIOUS_INTERRUPT)].set_handler_fn(spurious_interrupt_handler);
 
+        // the timer is driven through the scheduler, which still runs the IRQ handlers
         unsafe {
-            table.double_fault
-                .set_handler_fn(handle_double_fault_exception)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            table[InterruptIndex::Timer.as_usize()]
+                .set_handler_addr(VirtAddr::new(timer_interrupt_entry as *const () as u64));
+            table[usize::from(YIELD_INTERRUPT)]
+                .set_handler_addr(VirtAddr::new(yield_interrupt_entry as *const () as u64));
         }
 
-        table[InterruptIndex::Timer.as_usize()].set_handler_fn(handle_timer_interrupt);
-        table[InterruptIndex::Keyboard.as_usize()].set_handler_fn(handle_keyboard_interrupt);
-
         table
     };
 }
@@ -41,6 +61,11 @@ impl InterruptIndex {
         self as u8
     }
 
+    /// Returns the number of the IRQ line.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
@@ -50,69 +75,83 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Initializes the PICs with only the timer and the cascade line unmasked.
+///
+/// Other lines are unmasked once a handler is registered for them.
 pub fn init_pics() {
-    unsafe { PICS.lock().initialize() };
-}
-
-extern "x86-interrupt" fn handle_breakpoint_exception(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
+    // timer on line 0 and the secondary PIC on line 2
+    const PRIMARY_MASK: u8 = !0b0000_0101;
+    const SECONDARY_MASK: u8 = u8::MAX;
 
-extern "x86-interrupt" fn handle_page_fault_exception(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
-    use x86_64::registers::control::Cr2;
+    let mut pics = PICS.lock();
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    crate::hlt_loop();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(PRIMARY_MASK, SECONDARY_MASK);
+    }
 }
 
-extern "x86-interrupt" fn handle_double_fault_exception(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
-    println!("EXCEPTION: DOUBLE FAULT");
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    crate::hlt_loop();
-}
+/// Masks every line of the PICs, returning a bitmap of the IRQs which were
+/// unmasked before.
+pub(crate) fn disable_pics() -> u16 {
+    let mut pics = PICS.lock();
+    let masks = unsafe { pics.read_masks() };
 
-extern "x86-interrupt" fn handle_timer_interrupt(_stack_frame: InterruptStackFrame) {
-    print!(".");
+    unsafe { pics.disable() };
 
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8())
-    };
+    !u16::from_le_bytes(masks)
 }
 
-extern "x86-interrupt" fn handle_keyboard_interrupt(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
+/// Reads the in-service registers of the PICs, bit `n` belonging to IRQ `n`.
+fn pic_in_service() -> u16 {
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(ScancodeSet1::new(), layouts::Us104Key, HandleControl::Ignore));
+    const PRIMARY_COMMAND: u16 = 0x20;
+    const SECONDARY_COMMAND: u16 = 0xa0;
+    /// OCW3 selecting the in-service register for the next read.
+    const READ_ISR: u8 = 0x0b;
+
+    // the lock keeps others from using the PICs in between
+    let _pics = PICS.lock();
+    let mut primary = Port::<u8>::new(PRIMARY_COMMAND);
+    let mut secondary = Port::<u8>::new(SECONDARY_COMMAND);
+
+    unsafe {
+        primary.write(READ_ISR);
+        secondary.write(READ_ISR);
+        u16::from_le_bytes([primary.read(), secondary.read()])
     }
+}
 
-    let mut port = Port::new(0x60);
-    let mut keyboard = KEYBOARD.lock();
-    let scancode: u8 = unsafe { port.read() };
-
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => {
-                    print!("{}", character);
-                }
-                DecodedKey::RawKey(key) => {
-                    print!("{:?}", key);
-                }
-            }
-        }
+/// Signals the end of the given IRQ to the interrupt controller in use.
+fn end_of_interrupt(irq: u8) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) };
     }
+}
 
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8())
-    };
+extern "x86-interrupt" fn timer_event_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // only routed through the I/O APIC
+    timer::handle_interrupt();
+    apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // spurious interrupts must not be acknowledged
+}
+
+context_switch_entry!(timer_interrupt_entry, handle_timer_interrupt);
+context_switch_entry!(yield_interrupt_entry, scheduler::schedule);
+
+extern "C" fn handle_timer_interrupt(context: u64) -> u64 {
+    time::tick();
+    timer::handle_tick();
+    irq::run_handlers(InterruptIndex::Timer.irq());
+    end_of_interrupt(InterruptIndex::Timer.irq());
+
+    scheduler::schedule(context)
 }
 
 #[cfg(test)]
@@ -121,4 +160,39 @@ mod tests {
     fn test_breakpoint_exception() {
         x86_64::instructions::interrupts::int3();
     }
+
+    #[test_case]
+    fn test_overflow_exception_restores_registers() {
+        let value: u64;
+        unsafe {
+            // r11 is caller-saved, so the handler is free to clobber it
+            core::arch::asm!("int 4", inout("r11") 0x1234_5678_u64 => value);
+        }
+        assert_eq!(value, 0x1234_5678);
+    }
+
+    #[test_case]
+    fn test_register_invalid_irq() {
+        use super::{register_irq, IrqError, IRQ_COUNT};
+
+        fn handler(_irq: u8) {}
+
+        assert_eq!(register_irq(IRQ_COUNT, handler), Err(IrqError::InvalidIrq(IRQ_COUNT)));
+    }
+
+    #[test_case]
+    fn test_allocate_vector() {
+        use super::{allocate_vector, free_vector, VectorError, FIRST_DYNAMIC_VECTOR};
+
+        fn handler(_vector: u8) {}
+
+        let vector = allocate_vector(handler).unwrap();
+        let other = allocate_vector(handler).unwrap();
+        assert!(vector >= FIRST_DYNAMIC_VECTOR);
+        assert_ne!(vector, other);
+
+        assert_eq!(free_vector(other), Ok(()));
+        assert_eq!(free_vector(vector), Ok(()));
+        assert_eq!(free_vector(vector), Err(VectorError::InvalidVector(vector)));
+    }
 }
@@ -0,0 +1,354 @@
+use x86_64::structures::idt::{
+    ExceptionVector, InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode,
+    SelectorErrorCode,
+};
+use x86_64::VirtAddr;
+use core::fmt::{self, Write};
+use crate::{gdt, println, serial, vga_buffer};
+
+/// Installs a handler for every architectural CPU exception.
+pub(super) fn set_handlers(table: &mut InterruptDescriptorTable) {
+    table.breakpoint.set_handler_fn(handle_breakpoint_exception);
+
+    unsafe {
+        table.debug.set_handler_addr(entry_address(debug_entry));
+        table.non_maskable_interrupt.set_handler_addr(entry_address(non_maskable_interrupt_entry));
+        table.overflow.set_handler_addr(entry_address(overflow_entry));
+
+        table.divide_error.set_handler_addr(entry_address(divide_error_entry));
+        table.bound_range_exceeded.set_handler_addr(entry_address(bound_range_exceeded_entry));
+        table.invalid_opcode.set_handler_addr(entry_address(invalid_opcode_entry));
+        table.device_not_available.set_handler_addr(entry_address(device_not_available_entry));
+        table.invalid_tss.set_handler_addr(entry_address(invalid_tss_entry));
+        table.segment_not_present.set_handler_addr(entry_address(segment_not_present_entry));
+        table.stack_segment_fault.set_handler_addr(entry_address(stack_segment_fault_entry));
+        table.general_protection_fault.set_handler_addr(entry_address(general_protection_fault_entry));
+        table.page_fault.set_handler_addr(entry_address(page_fault_entry));
+        table.x87_floating_point.set_handler_addr(entry_address(x87_floating_point_entry));
+        table.alignment_check.set_handler_addr(entry_address(alignment_check_entry));
+        table.machine_check.set_handler_addr(entry_address(machine_check_entry));
+        table.simd_floating_point.set_handler_addr(entry_address(simd_floating_point_entry));
+        table.virtualization.set_handler_addr(entry_address(virtualization_entry));
+        table.cp_protection_exception.set_handler_addr(entry_address(control_protection_entry));
+        table.hv_injection_exception.set_handler_addr(entry_address(hypervisor_injection_entry));
+        table.vmm_communication_exception.set_handler_addr(entry_address(vmm_communication_entry));
+        table.security_exception.set_handler_addr(entry_address(security_entry));
+
+        table.double_fault
+            .set_handler_addr(entry_address(double_fault_entry))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+}
+
+fn entry_address(entry: extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(entry as *const () as u64)
+}
+
+/// General purpose registers at the time of an exception, in the order in
+/// which `save_exception_state` pushes them.
+#[derive(Debug)]
+#[repr(C)]
+struct Registers {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+/// Stack contents passed to `handle_fatal_exception` and `handle_exception`.
+#[repr(C)]
+struct ExceptionFrame {
+    registers: Registers,
+    vector: u64,
+    /// Error code pushed by the CPU, or 0 for exceptions without one.
+    error_code: u64,
+    // pushed by the CPU on exception entry
+    stack_frame: InterruptStackFrameValue,
+}
+
+/// Information which is printed in addition to the state of the CPU.
+#[derive(Debug, Clone, Copy)]
+enum Details {
+    None,
+    ErrorCode,
+    Selector,
+    PageFault,
+    MachineCheck,
+}
+
+/// Exceptions which can't be recovered from.
+const FATAL_EXCEPTIONS: [(ExceptionVector, &str, Details); 19] = [
+    (ExceptionVector::Division, "DIVIDE ERROR (#DE)", Details::None),
+    (ExceptionVector::BoundRange, "BOUND RANGE EXCEEDED (#BR)", Details::None),
+    (ExceptionVector::InvalidOpcode, "INVALID OPCODE (#UD)", Details::None),
+    (ExceptionVector::DeviceNotAvailable, "DEVICE NOT AVAILABLE (#NM)", Details::None),
+    (ExceptionVector::Double, "DOUBLE FAULT", Details::ErrorCode),
+    (ExceptionVector::InvalidTss, "INVALID TSS (#TS)", Details::Selector),
+    (ExceptionVector::SegmentNotPresent, "SEGMENT NOT PRESENT (#NP)", Details::Selector),
+    (ExceptionVector::Stack, "STACK SEGMENT FAULT (#SS)", Details::Selector),
+    (ExceptionVector::GeneralProtection, "GENERAL PROTECTION FAULT (#GP)", Details::Selector),
+    (ExceptionVector::Page, "PAGE FAULT", Details::PageFault),
+    (ExceptionVector::X87FloatingPoint, "X87 FLOATING POINT (#MF)", Details::None),
+    (ExceptionVector::AlignmentCheck, "ALIGNMENT CHECK (#AC)", Details::ErrorCode),
+    (ExceptionVector::MachineCheck, "MACHINE CHECK (#MC)", Details::MachineCheck),
+    (ExceptionVector::SimdFloatingPoint, "SIMD FLOATING POINT (#XM)", Details::None),
+    (ExceptionVector::Virtualization, "VIRTUALIZATION (#VE)", Details::None),
+    (ExceptionVector::ControlProtection, "CONTROL PROTECTION (#CP)", Details::ErrorCode),
+    (ExceptionVector::HypervisorInjection, "HYPERVISOR INJECTION (#HV)", Details::None),
+    (ExceptionVector::VmmCommunication, "VMM COMMUNICATION (#VC)", Details::ErrorCode),
+    (ExceptionVector::Security, "SECURITY (#SX)", Details::ErrorCode),
+];
+
+/// Defines a naked entry point for an exception, which pushes the vector and
+/// continues in `save_exception_state`, or in `save_resumable_state` for
+/// exceptions which return to the interrupted code.
+///
+/// Exceptions without an error code push 0 in its place, so that every
+/// exception leaves the same `ExceptionFrame` on the stack.
+macro_rules! exception_entry {
+    ($name:ident, $vector:expr) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "push 0",
+                "push {vector}",
+                "jmp {save}",
+                vector = const $vector as u8,
+                save = sym save_exception_state,
+            );
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "push {vector}",
+                "jmp {save}",
+                vector = const $vector as u8,
+                save = sym save_exception_state,
+            );
+        }
+    };
+    ($name:ident, $vector:expr, resumable) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "push 0",
+                "push {vector}",
+                "jmp {save}",
+                vector = const $vector as u8,
+                save = sym save_resumable_state,
+            );
+        }
+    };
+}
+
+exception_entry!(debug_entry, ExceptionVector::Debug, resumable);
+exception_entry!(non_maskable_interrupt_entry, ExceptionVector::NonMaskableInterrupt, resumable);
+exception_entry!(overflow_entry, ExceptionVector::Overflow, resumable);
+
+exception_entry!(divide_error_entry, ExceptionVector::Division);
+exception_entry!(bound_range_exceeded_entry, ExceptionVector::BoundRange);
+exception_entry!(invalid_opcode_entry, ExceptionVector::InvalidOpcode);
+exception_entry!(device_not_available_entry, ExceptionVector::DeviceNotAvailable);
+exception_entry!(double_fault_entry, ExceptionVector::Double, error_code);
+exception_entry!(invalid_tss_entry, ExceptionVector::InvalidTss, error_code);
+exception_entry!(segment_not_present_entry, ExceptionVector::SegmentNotPresent, error_code);
+exception_entry!(stack_segment_fault_entry, ExceptionVector::Stack, error_code);
+exception_entry!(general_protection_fault_entry, ExceptionVector::GeneralProtection, error_code);
+exception_entry!(page_fault_entry, ExceptionVector::Page, error_code);
+exception_entry!(x87_floating_point_entry, ExceptionVector::X87FloatingPoint);
+exception_entry!(alignment_check_entry, ExceptionVector::AlignmentCheck, error_code);
+exception_entry!(machine_check_entry, ExceptionVector::MachineCheck);
+exception_entry!(simd_floating_point_entry, ExceptionVector::SimdFloatingPoint);
+exception_entry!(virtualization_entry, ExceptionVector::Virtualization);
+exception_entry!(control_protection_entry, ExceptionVector::ControlProtection, error_code);
+exception_entry!(hypervisor_injection_entry, ExceptionVector::HypervisorInjection);
+exception_entry!(vmm_communication_entry, ExceptionVector::VmmCommunication, error_code);
+exception_entry!(security_entry, ExceptionVector::Security, error_code);
+
+/// Pushes the general purpose registers, completing the `ExceptionFrame`, and
+/// calls `handle_fatal_exception` with a pointer to it.
+#[unsafe(naked)]
+extern "C" fn save_exception_state() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        // the handler expects an aligned stack
+        "and rsp, -16",
+        "call {handler}",
+        "ud2",
+        handler = sym handle_fatal_exception,
+    );
+}
+
+/// Like `save_exception_state`, but calls `handle_exception` and returns to the
+/// interrupted code with all registers restored.
+#[unsafe(naked)]
+extern "C" fn save_resumable_state() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        // rbx is preserved by the handler and already saved
+        "mov rbx, rsp",
+        "and rsp, -16",
+        "call {handler}",
+        "mov rsp, rbx",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        // skip the vector and the error code
+        "add rsp, 16",
+        "iretq",
+        handler = sym handle_exception,
+    );
+}
+
+extern "C" fn handle_fatal_exception(frame: &ExceptionFrame) -> ! {
+    let (description, details) = FATAL_EXCEPTIONS
+        .iter()
+        .find(|(vector, ..)| *vector as u64 == frame.vector)
+        .map(|&(_, description, details)| (description, details))
+        .unwrap_or(("UNKNOWN", Details::ErrorCode));
+
+    println!("EXCEPTION: {}", description);
+    match details {
+        Details::None => {}
+        Details::ErrorCode => {
+            println!("Error Code: {:#x}", frame.error_code);
+        }
+        Details::Selector => print_selector_error(frame.error_code),
+        Details::PageFault => {
+            use x86_64::registers::control::Cr2;
+
+            println!("Accessed Address: {:?}", Cr2::read());
+            println!("Error Code: {:?}", PageFaultErrorCode::from_bits_truncate(frame.error_code));
+        }
+        Details::MachineCheck => {
+            use x86_64::registers::model_specific::Msr;
+
+            const IA32_MCG_STATUS: u32 = 0x17a;
+
+            println!("MCG_STATUS: {:#x}", unsafe { Msr::new(IA32_MCG_STATUS).read() });
+        }
+    }
+
+    print_vga_state(frame);
+    crate::hlt_loop();
+}
+
+/// Reports an exception after which the interrupted code continues.
+extern "C" fn handle_exception(frame: &ExceptionFrame) {
+    use x86_64::registers::debug::Dr6;
+
+    if frame.vector == ExceptionVector::NonMaskableInterrupt as u64 {
+        // an NMI can arrive while any lock is held, so it is only reported if
+        // the serial port is free
+        if let Some(mut serial) = serial::SERIAL1.try_lock() {
+            let _ = writeln!(serial, "EXCEPTION: NON-MASKABLE INTERRUPT");
+            let _ = print_state(&mut *serial, &frame.stack_frame, &frame.registers);
+        }
+    } else if frame.vector == ExceptionVector::Debug as u64 {
+        println!("EXCEPTION: DEBUG (#DB)");
+        println!("DR6: {:?}", Dr6::read());
+        print_vga_state(frame);
+    } else {
+        println!("EXCEPTION: OVERFLOW (#OF)");
+        print_vga_state(frame);
+    }
+}
+
+extern "x86-interrupt" fn handle_breakpoint_exception(stack_frame: InterruptStackFrame) {
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+/// Decodes the error code of exceptions which refer to a segment selector.
+fn print_selector_error(error_code: u64) {
+    match SelectorErrorCode::new(error_code) {
+        Some(selector) if selector.is_null() => {
+            println!("Error Code: 0 (not caused by a selector)");
+        }
+        Some(selector) => {
+            println!(
+                "Selector: index {} in {:?}{}",
+                selector.index(),
+                selector.descriptor_table(),
+                if selector.external() { " (external event)" } else { "" }
+            );
+        }
+        None => {
+            println!("Error Code: {:#x} (reserved bits set)", error_code);
+        }
+    }
+}
+
+/// Prints the state of the CPU at the time of `frame` to the VGA buffer.
+fn print_vga_state(frame: &ExceptionFrame) {
+    let _ = print_state(&mut *vga_buffer::WRITER.lock(), &frame.stack_frame, &frame.registers);
+}
+
+/// Writes the interrupted stack frame and the general purpose registers
+/// together with the control registers to `out`.
+fn print_state(out: &mut impl Write, stack_frame: &InterruptStackFrameValue, registers: &Registers) -> fmt::Result {
+    use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+
+    writeln!(out, "{:#?}", stack_frame)?;
+    writeln!(out, "RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}", registers.rax, registers.rbx, registers.rcx)?;
+    writeln!(out, "RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}", registers.rdx, registers.rsi, registers.rdi)?;
+    writeln!(out, "RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}", registers.rbp, registers.r8, registers.r9)?;
+    writeln!(out, "R10: {:#018x}  R11: {:#018x}  R12: {:#018x}", registers.r10, registers.r11, registers.r12)?;
+    writeln!(out, "R13: {:#018x}  R14: {:#018x}  R15: {:#018x}", registers.r13, registers.r14, registers.r15)?;
+    writeln!(out, "CR0: {:#018x}  CR2: {:#018x}", Cr0::read_raw(), Cr2::read().as_u64())?;
+    writeln!(out, "CR3: {:#018x}  CR4: {:#018x}", Cr3::read().0.start_address().as_u64(), Cr4::read_raw())
+}
@@ -0,0 +1,172 @@
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
+use spin::Mutex;
+use crate::apic;
+use super::{PICS, PIC_1_OFFSET};
+
+/// Number of legacy IRQ lines provided by the chained PICs.
+pub const IRQ_COUNT: u8 = 16;
+/// Maximum number of handlers which can share a single IRQ line.
+pub const MAX_HANDLERS_PER_IRQ: usize = 4;
+/// Line of the primary PIC to which the secondary PIC is connected.
+const CASCADE_IRQ: u8 = 2;
+/// Lowest priority line of each PIC, which it raises for requests that went
+/// away before they were acknowledged.
+const SPURIOUS_IRQS: [u8; 2] = [7, 15];
+
+/// Device interrupt handler, called with the number of the IRQ which fired.
+///
+/// Handlers run in interrupt context with interrupts disabled, so they must not
+/// block. End of interrupt is signalled after all handlers of a line have run.
+pub type IrqHandler = fn(irq: u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ number is not a valid legacy IRQ line.
+    InvalidIrq(u8),
+    /// The maximum number of handlers is already registered on the line.
+    LineFull(u8),
+}
+
+static HANDLERS: Mutex<[[Option<IrqHandler>; MAX_HANDLERS_PER_IRQ]; IRQ_COUNT as usize]> =
+    Mutex::new([[None; MAX_HANDLERS_PER_IRQ]; IRQ_COUNT as usize]);
+
+/// Registers `handler` to be called whenever the given IRQ fires and unmasks the
+/// IRQ line.
+///
+/// Several handlers can be registered on the same line, in which case all of
+/// them are called in the order of registration.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if irq >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slot = handlers[usize::from(irq)]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::LineFull(irq))?;
+
+        *slot = Some(handler);
+        Ok(())
+    })?;
+
+    unmask_irq(irq)
+}
+
+/// Stops the interrupt controller from delivering the given IRQ.
+pub fn mask_irq(irq: u8) -> Result<(), IrqError> {
+    set_masked(irq, true)
+}
+
+/// Returns whether the interrupt controller keeps the given IRQ from being
+/// delivered.
+pub fn is_irq_masked(irq: u8) -> Result<bool, IrqError> {
+    if irq >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+
+    if apic::is_enabled() {
+        return Ok(apic::is_irq_masked(irq));
+    }
+
+    let masks = without_interrupts(|| unsafe { PICS.lock().read_masks() });
+    Ok(masks[usize::from(irq / 8)] & (1 << (irq % 8)) != 0)
+}
+
+/// Allows the interrupt controller to deliver the given IRQ.
+pub fn unmask_irq(irq: u8) -> Result<(), IrqError> {
+    set_masked(irq, false)?;
+
+    // lines of the secondary PIC only arrive through the cascade line
+    if irq >= 8 {
+        set_masked(CASCADE_IRQ, false)?;
+    }
+
+    Ok(())
+}
+
+fn set_masked(irq: u8, masked: bool) -> Result<(), IrqError> {
+    if irq >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+
+    if apic::is_enabled() {
+        apic::set_irq_masked(irq, masked);
+        return Ok(());
+    }
+
+    let pic = usize::from(irq / 8);
+    let bit = 1 << (irq % 8);
+
+    without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let mut masks = unsafe { pics.read_masks() };
+
+        if masked {
+            masks[pic] |= bit;
+        } else {
+            masks[pic] &= !bit;
+        }
+
+        unsafe { pics.write_masks(masks[0], masks[1]) };
+    });
+
+    Ok(())
+}
+
+/// Calls every handler registered for the given IRQ.
+///
+/// Must be called from interrupt context.
+pub(super) fn run_handlers(irq: u8) {
+    // copy the handlers, so that they are able to register other handlers
+    let handlers = HANDLERS.lock()[usize::from(irq)];
+
+    for handler in handlers.iter().flatten() {
+        handler(irq);
+    }
+}
+
+/// Returns whether the PICs raised the IRQ without it being in service.
+fn is_spurious(irq: u8) -> bool {
+    !apic::is_enabled() && SPURIOUS_IRQS.contains(&irq) && super::pic_in_service() & (1 << irq) == 0
+}
+
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        // the primary PIC did deliver the cascade line for a spurious IRQ of
+        // the secondary one, which has nothing to acknowledge
+        if irq >= 8 {
+            super::end_of_interrupt(CASCADE_IRQ);
+        }
+        return;
+    }
+
+    run_handlers(irq);
+    super::end_of_interrupt(irq);
+}
+
+/// Defines an interrupt entry point for each of the given IRQs, which forwards
+/// to `dispatch`.
+macro_rules! irq_entries {
+    ($($irq:literal),*) => {
+        [$({
+            extern "x86-interrupt" fn entry(_stack_frame: InterruptStackFrame) {
+                dispatch($irq);
+            }
+
+            entry as HandlerFunc
+        }),*]
+    };
+}
+
+/// Points the IDT entries of all legacy IRQs to the dispatching entry points.
+pub(super) fn set_handlers(table: &mut InterruptDescriptorTable) {
+    let entries: [HandlerFunc; IRQ_COUNT as usize] =
+        irq_entries!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+
+    for (irq, entry) in entries.into_iter().enumerate() {
+        table[usize::from(PIC_1_OFFSET) + irq].set_handler_fn(entry);
+    }
+}
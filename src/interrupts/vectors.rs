@@ -0,0 +1,93 @@
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
+use spin::Mutex;
+use crate::apic;
+
+/// First IDT vector handed out by `allocate_vector`.
+pub const FIRST_DYNAMIC_VECTOR: u8 = 64;
+/// Number of IDT vectors which can be allocated for message signalled interrupts.
+pub const DYNAMIC_VECTOR_COUNT: u8 = 32;
+
+/// Handler of an allocated vector, called with the vector number.
+///
+/// Handlers run in interrupt context with interrupts disabled, so they must not
+/// block. End of interrupt is signalled to the local APIC after the handler ran.
+pub type VectorHandler = fn(vector: u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorError {
+    /// All dynamic vectors are in use.
+    Exhausted,
+    /// The vector is not an allocated dynamic vector.
+    InvalidVector(u8),
+}
+
+static HANDLERS: Mutex<[Option<VectorHandler>; DYNAMIC_VECTOR_COUNT as usize]> =
+    Mutex::new([None; DYNAMIC_VECTOR_COUNT as usize]);
+
+/// Reserves an IDT vector and registers `handler` for it.
+///
+/// The vector is only raised by devices which are programmed to send it, such
+/// as PCI devices using MSI, which requires the local APIC.
+pub fn allocate_vector(handler: VectorHandler) -> Result<u8, VectorError> {
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let index = handlers.iter().position(Option::is_none).ok_or(VectorError::Exhausted)?;
+
+        handlers[index] = Some(handler);
+        Ok(FIRST_DYNAMIC_VECTOR + index as u8)
+    })
+}
+
+/// Releases a vector returned by `allocate_vector`.
+///
+/// The device raising the vector must have been stopped before.
+pub fn free_vector(vector: u8) -> Result<(), VectorError> {
+    let index = vector
+        .checked_sub(FIRST_DYNAMIC_VECTOR)
+        .filter(|&index| index < DYNAMIC_VECTOR_COUNT)
+        .ok_or(VectorError::InvalidVector(vector))?;
+
+    without_interrupts(|| {
+        HANDLERS.lock()[usize::from(index)]
+            .take()
+            .map(|_| ())
+            .ok_or(VectorError::InvalidVector(vector))
+    })
+}
+
+fn dispatch(index: u8) {
+    let handler = HANDLERS.lock()[usize::from(index)];
+
+    if let Some(handler) = handler {
+        handler(FIRST_DYNAMIC_VECTOR + index);
+    }
+
+    apic::end_of_interrupt();
+}
+
+/// Defines an interrupt entry point for each of the given vector indices, which
+/// forwards to `dispatch`.
+macro_rules! vector_entries {
+    ($($index:literal),*) => {
+        [$({
+            extern "x86-interrupt" fn entry(_stack_frame: InterruptStackFrame) {
+                dispatch($index);
+            }
+
+            entry as HandlerFunc
+        }),*]
+    };
+}
+
+/// Points the IDT entries of all dynamic vectors to the dispatching entry points.
+pub(super) fn set_handlers(table: &mut InterruptDescriptorTable) {
+    let entries: [HandlerFunc; DYNAMIC_VECTOR_COUNT as usize] = vector_entries!(
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28,
+        29, 30, 31
+    );
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        table[usize::from(FIRST_DYNAMIC_VECTOR) + index].set_handler_fn(entry);
+    }
+}
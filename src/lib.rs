@@ -14,6 +14,17 @@ pub mod interrupts;
 pub mod gdt;
 pub mod memory;
 pub mod allocator;
+pub mod task;
+pub mod thread;
+pub mod acpi;
+pub mod apic;
+pub mod time;
+pub mod timer;
+pub mod power;
+pub mod pci;
+pub mod virtio;
+pub mod block;
+pub mod vfs;
 mod testing;
 
 pub use testing::{test_runner, test_panic_handler};
@@ -47,5 +58,6 @@ pub fn init() {
     gdt::init_gdt();
     interrupts::init_idt();
     interrupts::init_pics();
+    time::init();
     x86_64::instructions::interrupts::enable();
 }
@@ -10,6 +10,9 @@ use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
 use kernel::println;
 
+/// Bytes of file data the root tmpfs can hold.
+const ROOT_TMPFS_SIZE: usize = 1024 * 1024;
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -26,20 +29,42 @@ fn panic(info: &PanicInfo) -> ! {
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    use alloc::sync::Arc;
     use x86_64::VirtAddr;
-    use kernel::{memory, allocator};
+    use kernel::{memory, allocator, apic, pci, time, timer, vfs};
+    use kernel::vfs::tmpfs::Tmpfs;
+    use kernel::task::{executor::Executor, keyboard, Task};
 
     kernel::init();
 
     let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut memory_mapper = unsafe { memory::get_memory_mapper(memory_offset) };
-    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::new(&boot_info.memory_map) };
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().expect("Heap initialization failed");
+
+    if let Err(error) = apic::init() {
+        println!("APIC unavailable, using the 8259 PICs: {:?}", error);
+    }
+    if let Err(error) = time::hpet::init() {
+        println!("HPET unavailable: {:?}", error);
+    } else if let Err(error) = timer::init() {
+        println!("Timer events limited to the tick rate: {:?}", error);
+    }
 
-    allocator::init_heap(&mut memory_mapper, &mut frame_allocator).expect("Heap initialization failed");
+    if let Err(error) = pci::init() {
+        println!("PCIe extended configuration space unavailable: {:?}", error);
+    }
+
+    if let Err(error) = vfs::mount("/", Arc::new(Tmpfs::new(ROOT_TMPFS_SIZE))) {
+        println!("Mounting the root file system failed: {:?}", error);
+    }
 
     #[cfg(test)]
     test_main();
-    kernel::hlt_loop();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.run();
 }
 
 #[test_case]
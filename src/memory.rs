@@ -1,38 +1,404 @@
+use core::slice;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use x86_64::structures::paging::{
     page_table::PageTableEntry,
-    OffsetPageTable, PageTable, FrameAllocator, PhysFrame, Size4KiB
+    frame::PhysFrameRange,
+    mapper::{MapToError, Translate, TranslateResult},
+    OffsetPageTable, PageTable, PageTableFlags, PageSize, FrameAllocator, FrameDeallocator, Mapper, Page,
+    PhysFrame, Size4KiB, Size2MiB
 };
 use x86_64::{PhysAddr, VirtAddr};
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 
+/// Number of 4 KiB frames in a single 2 MiB frame.
+const FRAMES_PER_HUGE_FRAME: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize;
+
+/// Physical frame allocator which keeps track of every usable frame from the
+/// bootloader memory map in a bitmap, so that frames can be freed again.
 pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
+    /// One bit per 4 KiB frame, set if the frame is in use or not usable at all.
+    bitmap: &'static mut [u64],
+    total_frames: usize,
+    used_frames: usize,
+    /// Frame index at which to start looking for the next free frame.
     next: usize,
 }
 
 impl BootInfoFrameAllocator {
-    pub unsafe fn new(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
+    /// Creates a frame allocator from the given memory map.
+    ///
+    /// The bitmap itself is placed at the start of the first usable region large
+    /// enough to hold it, and the frames it occupies are marked as used.
+    ///
+    /// # Safety
+    ///
+    /// All frames marked as `Usable` in the memory map must really be unused,
+    /// for as long as the allocator hands them out. The complete physical
+    /// memory must stay mapped at `memory_offset`, since the bitmap is accessed
+    /// through that mapping. Creating a second allocator hands out the same
+    /// frames twice.
+    pub unsafe fn new(memory_map: &'static MemoryMap, memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map.iter()
+                .filter(|region| region.region_type == MemoryRegionType::Usable)
+        };
+
+        let frame_count = usable_regions()
+            .map(|region| region.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+        let bitmap_words = frame_count.div_ceil(64);
+        let bitmap_size = (bitmap_words * 8) as u64;
+
+        let bitmap_start = usable_regions()
+            .find(|region| region.range.end_addr() - region.range.start_addr() >= bitmap_size)
+            .expect("No usable memory region can hold the frame bitmap")
+            .range
+            .start_addr();
+
+        let bitmap = unsafe {
+            let bitmap_ptr = (memory_offset + bitmap_start).as_mut_ptr::<u64>();
+            slice::from_raw_parts_mut(bitmap_ptr, bitmap_words)
+        };
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = BootInfoFrameAllocator {
+            bitmap,
+            total_frames: 0,
+            used_frames: 0,
             next: 0,
+        };
+
+        for region in usable_regions() {
+            let start = region.range.start_frame_number as usize;
+            let end = region.range.end_frame_number as usize;
+
+            for index in start..end {
+                allocator.set_bit(index, false);
+            }
+            allocator.total_frames += end - start;
+        }
+
+        let bitmap_frame = (bitmap_start / Size4KiB::SIZE) as usize;
+        let bitmap_frames = bitmap_size.div_ceil(Size4KiB::SIZE) as usize;
+        allocator.mark_used(bitmap_frame, bitmap_frames);
+
+        allocator
+    }
+
+    /// Returns the number of usable frames from the memory map.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Returns the number of frames which are currently allocated.
+    pub fn used_frames(&self) -> usize {
+        self.used_frames
+    }
+
+    /// Returns the number of frames which are still available.
+    pub fn free_frames(&self) -> usize {
+        self.total_frames - self.used_frames
+    }
+
+    /// Allocates `count` physically contiguous 4 KiB frames.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrameRange> {
+        let index = self.find_free_run(count, 1)?;
+        self.mark_used(index, count);
+
+        let start = frame_at(index);
+        Some(PhysFrame::range(start, start + count as u64))
+    }
+
+    /// Frees a range of frames returned by `allocate_contiguous`.
+    ///
+    /// # Safety
+    ///
+    /// The range must have been returned by `allocate_contiguous`, and none of
+    /// its frames may still be in use or mapped anywhere.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        let index = frame_index(range.start.start_address());
+        self.mark_free(index, range.count());
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, index: usize, used: bool) {
+        if used {
+            self.bitmap[index / 64] |= 1 << (index % 64);
+        } else {
+            self.bitmap[index / 64] &= !(1 << (index % 64));
         }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        self.memory_map.iter()
-            .filter(|region| region.region_type == MemoryRegionType::Usable)
-            .map(|region| region.range.start_addr()..region.range.end_addr())
-            .flat_map(|region| region.step_by(4096))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    fn mark_used(&mut self, index: usize, count: usize) {
+        for i in index..index + count {
+            assert!(!self.is_used(i), "Frame {} is already in use", i);
+            self.set_bit(i, true);
+        }
+        self.used_frames += count;
+    }
+
+    fn mark_free(&mut self, index: usize, count: usize) {
+        for i in index..index + count {
+            assert!(self.is_used(i), "Frame {} is already free", i);
+            self.set_bit(i, false);
+        }
+        self.used_frames -= count;
+
+        if index < self.next {
+            self.next = index;
+        }
+    }
+
+    /// Looks for a single free frame, starting at the position of the last
+    /// allocation and skipping fully used bitmap words.
+    fn find_free_frame(&self) -> Option<usize> {
+        let first_word = self.next / 64;
+
+        (first_word..self.bitmap.len())
+            .chain(0..first_word)
+            .find(|&word| self.bitmap[word] != u64::MAX)
+            .map(|word| word * 64 + (!self.bitmap[word]).trailing_zeros() as usize)
+    }
+
+    /// Looks for `count` free frames in a row, the first of which has an index
+    /// which is a multiple of `align`.
+    ///
+    /// Returns the index of the first frame of the run.
+    fn find_free_run(&self, count: usize, align: usize) -> Option<usize> {
+        let frame_count = self.bitmap.len() * 64;
+        let mut start = 0;
+
+        while start + count <= frame_count {
+            // check from the back so that we can skip past the last used frame
+            match (start..start + count).rev().find(|&i| self.is_used(i)) {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => return Some(start),
+            }
+        }
+
+        None
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        let index = self.find_free_frame()?;
+        self.mark_used(index, 1);
+        self.next = index + 1;
+
+        Some(frame_at(index))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.mark_free(frame_index(frame.start_address()), 1);
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let index = self.find_free_run(FRAMES_PER_HUGE_FRAME, FRAMES_PER_HUGE_FRAME)?;
+        self.mark_used(index, FRAMES_PER_HUGE_FRAME);
+
+        Some(PhysFrame::containing_address(frame_at(index).start_address()))
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.mark_free(frame_index(frame.start_address()), FRAMES_PER_HUGE_FRAME);
+    }
+}
+
+fn frame_index(addr: PhysAddr) -> usize {
+    (addr.as_u64() / Size4KiB::SIZE) as usize
+}
+
+fn frame_at(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * Size4KiB::SIZE))
+}
+
+/// Page table mapper and frame allocator shared by the kernel once `init` has
+/// been called.
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+/// Virtual address at which the bootloader mapped the physical memory.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Sets up the kernel-wide memory mapper and frame allocator.
+///
+/// # Safety
+///
+/// The complete physical memory must be mapped at `memory_offset` for the rest
+/// of the kernel's lifetime, and all regions marked as `Usable` in `memory_map`
+/// must really be unused. This function must be called only once, since the
+/// mapper takes ownership of the active level 4 page table.
+pub unsafe fn init(memory_offset: VirtAddr, memory_map: &'static MemoryMap) {
+    let mapper = unsafe { get_memory_mapper(memory_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::new(memory_map, memory_offset) };
+
+    PHYSICAL_MEMORY_OFFSET.store(memory_offset.as_u64(), Ordering::SeqCst);
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+}
+
+/// Returns the virtual address through which the given physical address can be
+/// accessed.
+///
+/// Only regions mapped by the bootloader or through `map_physical` and
+/// `map_mmio` are actually accessible.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) + addr.as_u64())
+}
+
+/// Makes sure that the given physical memory region, such as a firmware table,
+/// is accessible at `phys_to_virt(start)`.
+pub fn map_physical(start: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    map_physical_region(start, size, PageTableFlags::PRESENT)
+}
+
+/// Maps the registers of a memory mapped device at `phys_to_virt(start)` with
+/// caching disabled.
+///
+/// Pages which the bootloader already mapped are made writable and uncached,
+/// huge pages are split up so that the rest of them stays cached.
+pub fn map_mmio(start: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    map_physical_region(start, size, flags)
+}
+
+/// Maps the region with at least the given flags, adding missing flags to the
+/// pages which are mapped already.
+///
+/// Huge pages are only split if they lack some of the flags.
+fn map_physical_region(start: PhysAddr, size: u64, flags: PageTableFlags) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(start);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(start + size.max(1) - 1u64);
+
+    with_memory(|mapper, frame_allocator| {
+        for frame in PhysFrame::range_inclusive(first_frame, last_frame) {
+            let page = Page::containing_address(phys_to_virt(frame.start_address()));
+
+            match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(MapToError::PageAlreadyMapped(_)) => add_flags(mapper, page, flags)?,
+                Err(MapToError::ParentEntryHugePage) if !has_flags(mapper, page, flags) => {
+                    split_huge_pages(mapper, frame_allocator, page)?;
+                    add_flags(mapper, page, flags)?;
+                }
+                Err(MapToError::ParentEntryHugePage) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(phys_to_virt(start))
+    }).ok_or(MapToError::FrameAllocationFailed)?
+}
+
+/// Checks whether the given page is mapped with at least the given flags.
+fn has_flags(mapper: &OffsetPageTable, page: Page, flags: PageTableFlags) -> bool {
+    matches!(
+        mapper.translate(page.start_address()),
+        TranslateResult::Mapped { flags: current, .. } if current.contains(flags)
+    )
+}
+
+/// Adds the flags which are missing in the mapping of the given page.
+fn add_flags(mapper: &mut OffsetPageTable, page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let TranslateResult::Mapped { flags: current, .. } = mapper.translate(page.start_address()) else {
+        return Err(MapToError::ParentEntryHugePage);
+    };
+
+    if !current.contains(flags) {
+        // only fails for unmapped pages, which were checked above
+        unsafe { mapper.update_flags(page, current | flags) }
+            .map_err(|_| MapToError::ParentEntryHugePage)?
+            .flush();
     }
+
+    Ok(())
+}
+
+/// Replaces the huge pages which map `page` by tables of smaller pages with the
+/// same flags, so that `page` can be given its own flags.
+fn split_huge_pages(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BootInfoFrameAllocator,
+    page: Page,
+) -> Result<(), MapToError<Size4KiB>> {
+    use x86_64::structures::paging::Size1GiB;
+
+    let p4 = mapper.level_4_table();
+    let p3 = unsafe { next_table(&p4[page.p4_index()]) };
+
+    let p3_entry = &mut p3[page.p3_index()];
+    if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        split_huge_page(p3_entry, Size1GiB::SIZE, frame_allocator)?;
+    }
+
+    let p2 = unsafe { next_table(p3_entry) };
+    let p2_entry = &mut p2[page.p2_index()];
+    if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        split_huge_page(p2_entry, Size2MiB::SIZE, frame_allocator)?;
+    }
+
+    x86_64::instructions::tlb::flush_all();
+    Ok(())
+}
+
+/// Returns the table which the given present entry points to.
+///
+/// This function is unsafe because the caller must guarantee that the entry
+/// is not a huge page and that no other reference to the table exists.
+unsafe fn next_table(entry: &PageTableEntry) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>() }
+}
+
+/// Points the entry of a huge page of `size` bytes to a new table which maps
+/// the same memory with 512 smaller pages.
+fn split_huge_page(
+    entry: &mut PageTableEntry,
+    size: u64,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+    let table = unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() };
+
+    // the huge page bit is the PAT bit in the entries of 4 KiB pages
+    let page_size = size / 512;
+    let mut flags = entry.flags();
+    if page_size == Size4KiB::SIZE {
+        flags.remove(PageTableFlags::HUGE_PAGE);
+    }
+
+    for (index, page_entry) in table.iter_mut().enumerate() {
+        page_entry.set_addr(entry.addr() + index as u64 * page_size, flags);
+    }
+
+    // access is restricted by the entries of the new table
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (entry.flags() & PageTableFlags::USER_ACCESSIBLE);
+    entry.set_frame(frame, table_flags);
+    Ok(())
+}
+
+/// Runs `f` with the kernel memory mapper and frame allocator.
+///
+/// Returns `None` if `init` has not been called yet. `f` must not allocate on
+/// the heap, since the heap itself grows through this function.
+pub fn with_memory<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R,
+{
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        MEMORY.lock()
+            .as_mut()
+            .map(|(mapper, frame_allocator)| f(mapper, frame_allocator))
+    })
 }
 
 pub struct EmptyFrameAllocator;
@@ -43,6 +409,13 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// Returns a mapper for the active level 4 page table.
+///
+/// # Safety
+///
+/// The complete physical memory must be mapped at `memory_offset`. The
+/// returned mapper holds a `&'static mut` to the active level 4 table, so this
+/// function must be called only once.
 pub unsafe fn get_memory_mapper(memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     unsafe {
         let level_4_table = get_level_4_table(memory_offset);
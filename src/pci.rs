@@ -0,0 +1,135 @@
+pub mod config;
+pub mod device;
+pub mod msi;
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::println;
+
+pub use config::{EcamError, PciAddress};
+pub use device::{Bar, Capability, ExtendedCapability, PciDevice};
+
+pub const CLASS_MASS_STORAGE: u8 = 0x01;
+pub const CLASS_NETWORK: u8 = 0x02;
+pub const CLASS_DISPLAY: u8 = 0x03;
+pub const CLASS_BRIDGE: u8 = 0x06;
+
+pub const SUBCLASS_IDE: u8 = 0x01;
+pub const SUBCLASS_SATA: u8 = 0x06;
+pub const SUBCLASS_NVME: u8 = 0x08;
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+/// Every function found by `init`.
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// Enumerates all PCI functions and stores them in the device registry.
+///
+/// Uses the memory mapped configuration space described by the ACPI MCFG
+/// table if available, and the legacy I/O ports otherwise. Requires the heap
+/// and `memory::init`.
+pub fn init() -> Result<(), EcamError> {
+    let ecam = config::init_ecam();
+
+    let devices = scan();
+    without_interrupts(|| *DEVICES.lock() = devices);
+
+    ecam
+}
+
+/// Probes the buses of all segment groups, starting at the first bus of each
+/// and following PCI-to-PCI bridges.
+///
+/// Only buses which are actually present are touched, so that the
+/// configuration space of absent buses is never mapped.
+fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for (segment, start_bus, _) in config::bus_ranges() {
+        scan_bus(segment, start_bus, &mut devices);
+    }
+
+    devices
+}
+
+/// Probes every device and function of a bus and the buses behind its bridges.
+fn scan_bus(segment: u16, bus: u8, devices: &mut Vec<PciDevice>) {
+    for slot in 0..DEVICES_PER_BUS {
+        let address = PciAddress::new(segment, bus, slot, 0);
+        if config::read_u16(address, device::REG_VENDOR_ID) == u16::MAX {
+            continue;
+        }
+
+        let function_count = match config::read_u8(address, device::REG_HEADER_TYPE) {
+            header_type if header_type & device::HEADER_TYPE_MULTI_FUNCTION != 0 => FUNCTIONS_PER_DEVICE,
+            _ => 1,
+        };
+
+        for function in 0..function_count {
+            let Some(found) = PciDevice::probe(PciAddress::new(segment, bus, slot, function)) else {
+                continue;
+            };
+
+            // the firmware numbers the buses behind a bridge above its own bus,
+            // which also keeps this from looping
+            let secondary_bus = found.read_u8(device::REG_SECONDARY_BUS);
+            let is_bridge = found.header_type == device::HEADER_TYPE_PCI_BRIDGE && secondary_bus > bus;
+
+            devices.push(found);
+            if is_bridge {
+                scan_bus(segment, secondary_bus, devices);
+            }
+        }
+    }
+}
+
+/// Returns all functions found by `init`.
+pub fn devices() -> Vec<PciDevice> {
+    without_interrupts(|| DEVICES.lock().clone())
+}
+
+/// Returns the functions with the given class and subclass.
+pub fn find_by_class(class: u8, subclass: u8) -> Vec<PciDevice> {
+    find(|device| device.class == class && device.subclass == subclass)
+}
+
+/// Returns the functions with the given vendor and device ID.
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> Vec<PciDevice> {
+    find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// Returns the functions matching `predicate`.
+pub fn find(predicate: impl Fn(&PciDevice) -> bool) -> Vec<PciDevice> {
+    without_interrupts(|| DEVICES.lock().iter().filter(|device| predicate(device)).cloned().collect())
+}
+
+/// Prints a line for every function in the registry.
+pub fn dump() {
+    for device in devices() {
+        println!(
+            "{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x} irq {}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if,
+            device.interrupt_line,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config, device, PciAddress, CLASS_BRIDGE};
+
+    #[test_case]
+    fn test_host_bridge() {
+        let address = PciAddress::new(0, 0, 0, 0);
+
+        assert_ne!(config::read_u16(address, device::REG_VENDOR_ID), u16::MAX);
+        assert_eq!(config::read_u8(address, device::REG_CLASS), CLASS_BRIDGE);
+    }
+}
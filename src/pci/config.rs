@@ -0,0 +1,228 @@
+use core::{fmt, ptr};
+use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{self, AcpiError};
+use crate::memory;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+/// Size of the configuration space of a function with the legacy mechanism.
+pub const LEGACY_CONFIG_SIZE: u16 = 0x100;
+/// Size of the extended configuration space of a PCIe function.
+pub const EXTENDED_CONFIG_SIZE: u16 = 0x1000;
+/// Size of the memory mapped configuration space of a bus.
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+static LEGACY_PORTS: Mutex<()> = Mutex::new(());
+static ECAM_REGIONS: OnceCell<Vec<EcamRegion>> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcamError {
+    Acpi(AcpiError),
+    /// ECAM was initialized already.
+    AlreadyInitialized,
+}
+
+impl From<AcpiError> for EcamError {
+    fn from(error: AcpiError) -> Self {
+        EcamError::Acpi(error)
+    }
+}
+
+/// Memory mapped configuration space of the buses of a segment group.
+///
+/// The configuration space of a bus is only mapped on its first access, since
+/// mapping all buses up front takes 256 MiB of address space per segment.
+#[derive(Debug)]
+struct EcamRegion {
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    base: PhysAddr,
+    /// Bit per bus relative to `start_bus`, set once the bus is mapped.
+    mapped_buses: [AtomicU64; 4],
+}
+
+impl EcamRegion {
+    /// Returns the address of the given register, if the function is in this region.
+    ///
+    /// Returns `None` if the configuration space of the bus can't be mapped.
+    fn register(&self, address: PciAddress, offset: u16) -> Option<*mut u32> {
+        if address.segment != self.segment || !(self.start_bus..=self.end_bus).contains(&address.bus) {
+            return None;
+        }
+
+        let bus = self.map_bus(address.bus - self.start_bus)?;
+        let function = u64::from(address.device & 0x1f) << 15 | u64::from(address.function & 0x07) << 12;
+        Some((bus + function + u64::from(offset)).as_mut_ptr())
+    }
+
+    /// Returns the configuration space of the bus with the given index, mapping
+    /// it if this is the first access.
+    fn map_bus(&self, index: u8) -> Option<VirtAddr> {
+        let start = self.base + u64::from(index) * ECAM_BUS_SIZE;
+        let (word, bit) = (&self.mapped_buses[usize::from(index / 64)], 1 << (index % 64));
+
+        // mapping twice is harmless, so racing accesses may both map the bus
+        if word.load(Ordering::Acquire) & bit == 0 {
+            memory::map_mmio(start, ECAM_BUS_SIZE).ok()?;
+            word.fetch_or(bit, Ordering::Release);
+        }
+
+        Some(memory::phys_to_virt(start))
+    }
+}
+
+/// Switches to the PCIe extended configuration space of all segment groups
+/// described by the ACPI MCFG table.
+///
+/// Until this succeeds, configuration space is accessed through the legacy
+/// I/O ports. Buses are mapped on their first access, which requires the heap
+/// and `memory::init`.
+pub fn init_ecam() -> Result<(), EcamError> {
+    if ECAM_REGIONS.is_initialized() {
+        return Err(EcamError::AlreadyInitialized);
+    }
+
+    let regions = acpi::mcfg::parse()?
+        .into_iter()
+        .map(|region| EcamRegion {
+            segment: region.segment,
+            start_bus: region.start_bus,
+            end_bus: region.end_bus,
+            base: PhysAddr::new(region.base_address),
+            mapped_buses: [const { AtomicU64::new(0) }; 4],
+        })
+        .collect::<Vec<_>>();
+
+    ECAM_REGIONS.try_init_once(|| regions).map_err(|_| EcamError::AlreadyInitialized)
+}
+
+/// Returns the segment groups with their first and last bus.
+///
+/// Without ECAM, only the buses of segment 0 are reachable.
+pub fn bus_ranges() -> Vec<(u16, u8, u8)> {
+    match ECAM_REGIONS.get() {
+        Some(regions) => regions.iter().map(|region| (region.segment, region.start_bus, region.end_bus)).collect(),
+        None => alloc::vec![(0, 0, u8::MAX)],
+    }
+}
+
+/// Returns the size of the configuration space which can be accessed for the
+/// given function.
+pub fn config_size(address: PciAddress) -> u16 {
+    match ecam_register(address, 0) {
+        Some(_) => EXTENDED_CONFIG_SIZE,
+        None => LEGACY_CONFIG_SIZE,
+    }
+}
+
+fn ecam_register(address: PciAddress, offset: u16) -> Option<*mut u32> {
+    ECAM_REGIONS.get()?.iter().find_map(|region| region.register(address, offset))
+}
+
+/// Location of a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus, self.device, self.function)
+    }
+}
+
+/// Reads the aligned dword at `offset` of the configuration space of a function.
+pub fn read_u32(address: PciAddress, offset: u16) -> u32 {
+    let offset = offset & !0b11;
+
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return u32::MAX;
+    }
+    if let Some(register) = ecam_register(address, offset) {
+        return unsafe { ptr::read_volatile(register) };
+    }
+
+    // the legacy mechanism only reaches the first 256 bytes of segment 0
+    if address.segment != 0 || offset >= LEGACY_CONFIG_SIZE {
+        return u32::MAX;
+    }
+
+    without_interrupts(|| {
+        let _lock = LEGACY_PORTS.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(legacy_address(address, offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    })
+}
+
+/// Writes the aligned dword at `offset` of the configuration space of a function.
+pub fn write_u32(address: PciAddress, offset: u16, value: u32) {
+    let offset = offset & !0b11;
+
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return;
+    }
+    if let Some(register) = ecam_register(address, offset) {
+        unsafe { ptr::write_volatile(register, value) };
+        return;
+    }
+
+    if address.segment != 0 || offset >= LEGACY_CONFIG_SIZE {
+        return;
+    }
+
+    without_interrupts(|| {
+        let _lock = LEGACY_PORTS.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(legacy_address(address, offset));
+            Port::new(CONFIG_DATA).write(value);
+        }
+    })
+}
+
+pub fn read_u16(address: PciAddress, offset: u16) -> u16 {
+    (read_u32(address, offset) >> ((offset & 0b10) * 8)) as u16
+}
+
+pub fn read_u8(address: PciAddress, offset: u16) -> u8 {
+    (read_u32(address, offset) >> ((offset & 0b11) * 8)) as u8
+}
+
+/// Writes a word by reading and writing the surrounding dword.
+pub fn write_u16(address: PciAddress, offset: u16, value: u16) {
+    let shift = (offset & 0b10) * 8;
+    let dword = read_u32(address, offset) & !(0xffff << shift);
+    write_u32(address, offset, dword | u32::from(value) << shift);
+}
+
+fn legacy_address(address: PciAddress, offset: u16) -> u32 {
+    CONFIG_ENABLE
+        | u32::from(address.bus) << 16
+        | u32::from(address.device & 0x1f) << 11
+        | u32::from(address.function & 0x07) << 8
+        | u32::from(offset)
+}
@@ -0,0 +1,277 @@
+use alloc::vec::Vec;
+use super::config::{self, PciAddress};
+
+pub const REG_VENDOR_ID: u16 = 0x00;
+pub const REG_DEVICE_ID: u16 = 0x02;
+pub const REG_COMMAND: u16 = 0x04;
+pub const REG_STATUS: u16 = 0x06;
+pub const REG_REVISION: u16 = 0x08;
+pub const REG_PROG_IF: u16 = 0x09;
+pub const REG_SUBCLASS: u16 = 0x0a;
+pub const REG_CLASS: u16 = 0x0b;
+pub const REG_HEADER_TYPE: u16 = 0x0e;
+pub const REG_BAR0: u16 = 0x10;
+pub const REG_SECONDARY_BUS: u16 = 0x19;
+pub const REG_CAPABILITIES: u16 = 0x34;
+pub const REG_INTERRUPT_LINE: u16 = 0x3c;
+pub const REG_INTERRUPT_PIN: u16 = 0x3d;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+pub const HEADER_TYPE_GENERAL: u8 = 0x00;
+pub const HEADER_TYPE_PCI_BRIDGE: u8 = 0x01;
+pub const HEADER_TYPE_MULTI_FUNCTION: u8 = 1 << 7;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_MEMORY_64BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Offset of the first extended capability in PCIe configuration space.
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+
+/// Upper bound on the length of a capability list, to stop on malformed lists.
+const MAX_CAPABILITIES: usize = 48;
+const MAX_EXTENDED_CAPABILITIES: usize = (config::EXTENDED_CONFIG_SIZE - EXTENDED_CAPABILITIES) as usize / 4;
+
+/// Decoded Base Address Register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64bit: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+/// Entry of the capability list in configuration space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability in configuration space.
+    pub offset: u8,
+}
+
+/// Entry of the extended capability list of PCIe functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    /// Offset of the capability in extended configuration space.
+    pub offset: u16,
+}
+
+/// PCI function found during enumeration.
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// Header layout, without the multi-function bit.
+    pub header_type: u8,
+    pub bars: [Option<Bar>; 6],
+    /// Legacy IRQ configured by the firmware, `0xff` if unknown.
+    pub interrupt_line: u8,
+    /// Interrupt pin used by the function, 1 for INTA# up to 4 for INTD#, 0 if none.
+    pub interrupt_pin: u8,
+    pub capabilities: Vec<Capability>,
+    /// Only available through the memory mapped configuration space.
+    pub extended_capabilities: Vec<ExtendedCapability>,
+}
+
+impl PciDevice {
+    /// Reads the configuration space of the function at `address`, returning
+    /// `None` if there is no function.
+    pub fn probe(address: PciAddress) -> Option<Self> {
+        let vendor_id = config::read_u16(address, REG_VENDOR_ID);
+        if vendor_id == u16::MAX {
+            return None;
+        }
+
+        let header_type = config::read_u8(address, REG_HEADER_TYPE) & !HEADER_TYPE_MULTI_FUNCTION;
+        let bar_count = match header_type {
+            HEADER_TYPE_GENERAL => 6,
+            HEADER_TYPE_PCI_BRIDGE => 2,
+            _ => 0,
+        };
+
+        let mut device = PciDevice {
+            address,
+            vendor_id,
+            device_id: config::read_u16(address, REG_DEVICE_ID),
+            class: config::read_u8(address, REG_CLASS),
+            subclass: config::read_u8(address, REG_SUBCLASS),
+            prog_if: config::read_u8(address, REG_PROG_IF),
+            revision: config::read_u8(address, REG_REVISION),
+            header_type,
+            bars: [None; 6],
+            interrupt_line: config::read_u8(address, REG_INTERRUPT_LINE),
+            interrupt_pin: config::read_u8(address, REG_INTERRUPT_PIN),
+            capabilities: Vec::new(),
+            extended_capabilities: Vec::new(),
+        };
+
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, slots) = device.size_bar(index);
+            device.bars[index] = bar;
+            index += slots;
+        }
+
+        device.capabilities = device.read_capabilities();
+        device.extended_capabilities = device.read_extended_capabilities();
+        Some(device)
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        config::read_u32(self.address, offset)
+    }
+
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        config::write_u32(self.address, offset, value)
+    }
+
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        config::read_u16(self.address, offset)
+    }
+
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        config::write_u16(self.address, offset, value)
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        config::read_u8(self.address, offset)
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read_u16(REG_COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        // the status register shares the dword and its bits are cleared by writing ones
+        self.write_u32(REG_COMMAND, u32::from(command));
+    }
+
+    /// Enables decoding of the memory and I/O BARs and lets the device perform DMA.
+    pub fn enable_bus_mastering(&self) {
+        self.set_command(self.command() | COMMAND_MEMORY_SPACE | COMMAND_IO_SPACE | COMMAND_BUS_MASTER);
+    }
+
+    /// Returns the offset of the first capability with the given ID.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities.iter().find(|capability| capability.id == id).map(|capability| capability.offset)
+    }
+
+    /// Returns the offset of the first extended capability with the given ID.
+    pub fn find_extended_capability(&self, id: u16) -> Option<u16> {
+        self.extended_capabilities
+            .iter()
+            .find(|capability| capability.id == id)
+            .map(|capability| capability.offset)
+    }
+
+    /// Decodes the BAR at `index` and determines its size, returning the number
+    /// of BAR slots it occupies.
+    fn size_bar(&self, index: usize) -> (Option<Bar>, usize) {
+        let offset = REG_BAR0 + index as u16 * 4;
+        let value = self.read_u32(offset);
+
+        // stop decoding while the BAR holds the sizing pattern
+        let command = self.command();
+        self.set_command(command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE));
+
+        self.write_u32(offset, u32::MAX);
+        let mask = self.read_u32(offset);
+        self.write_u32(offset, value);
+
+        let (bar, slots) = if value & BAR_IO != 0 {
+            let size = (!(mask & !0b11)).wrapping_add(1) & 0xffff;
+            let bar = (mask != 0 && size != 0).then_some(Bar::Io { port: value & !0b11, size });
+            (bar, 1)
+        } else if value & BAR_MEMORY_64BIT != 0 {
+            let high_offset = offset + 4;
+            let high_value = self.read_u32(high_offset);
+            self.write_u32(high_offset, u32::MAX);
+            let high_mask = self.read_u32(high_offset);
+            self.write_u32(high_offset, high_value);
+
+            let address = u64::from(high_value) << 32 | u64::from(value & !0xf);
+            let mask = u64::from(high_mask) << 32 | u64::from(mask & !0xf);
+            let bar = (mask != 0).then_some(Bar::Memory {
+                address,
+                size: (!mask).wrapping_add(1),
+                prefetchable: value & BAR_PREFETCHABLE != 0,
+                is_64bit: true,
+            });
+            (bar, 2)
+        } else {
+            let mask = mask & !0xf;
+            let bar = (mask != 0).then_some(Bar::Memory {
+                address: u64::from(value & !0xf),
+                size: u64::from((!mask).wrapping_add(1)),
+                prefetchable: value & BAR_PREFETCHABLE != 0,
+                is_64bit: false,
+            });
+            (bar, 1)
+        };
+
+        self.set_command(command);
+        (bar, slots)
+    }
+
+    fn read_capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if self.read_u16(REG_STATUS) & STATUS_CAPABILITIES == 0 {
+            return capabilities;
+        }
+
+        let mut offset = self.read_u8(REG_CAPABILITIES) & !0b11;
+        while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+            let header = self.read_u16(u16::from(offset));
+            capabilities.push(Capability {
+                id: header as u8,
+                offset,
+            });
+            offset = (header >> 8) as u8 & !0b11;
+        }
+
+        capabilities
+    }
+
+    fn read_extended_capabilities(&self) -> Vec<ExtendedCapability> {
+        let mut capabilities = Vec::new();
+        if config::config_size(self.address) < config::EXTENDED_CONFIG_SIZE {
+            return capabilities;
+        }
+
+        let mut offset = EXTENDED_CAPABILITIES;
+        while offset >= EXTENDED_CAPABILITIES && capabilities.len() < MAX_EXTENDED_CAPABILITIES {
+            let header = self.read_u32(offset);
+            if header == 0 || header == u32::MAX {
+                break;
+            }
+
+            capabilities.push(ExtendedCapability {
+                id: header as u16,
+                version: ((header >> 16) & 0xf) as u8,
+                offset,
+            });
+            offset = (header >> 20) as u16 & !0b11;
+        }
+
+        capabilities
+    }
+}
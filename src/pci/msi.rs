@@ -0,0 +1,257 @@
+use core::ptr;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::interrupts::{self, VectorError, VectorHandler};
+use crate::{apic, memory};
+use super::config::{self, PciAddress};
+use super::device::{Bar, PciDevice, COMMAND_BUS_MASTER, COMMAND_INTERRUPT_DISABLE, COMMAND_MEMORY_SPACE};
+
+pub const CAPABILITY_MSI: u8 = 0x05;
+pub const CAPABILITY_MSIX: u8 = 0x11;
+
+const MSI_CONTROL: u16 = 0x02;
+const MSI_ADDRESS: u16 = 0x04;
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+
+const MSIX_CONTROL: u16 = 0x02;
+const MSIX_TABLE: u16 = 0x04;
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_ADDRESS_LOW: u64 = 0x0;
+const MSIX_ENTRY_ADDRESS_HIGH: u64 = 0x4;
+const MSIX_ENTRY_DATA: u64 = 0x8;
+const MSIX_ENTRY_VECTOR_CONTROL: u64 = 0xc;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Base of the address range decoded by local APICs.
+const MESSAGE_ADDRESS_BASE: u64 = 0xfee0_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    /// The device does not have the required capability.
+    NoCapability,
+    /// Message signalled interrupts are delivered by the local APIC, which is
+    /// not enabled.
+    ApicDisabled,
+    Vector(VectorError),
+    /// The MSI-X table is not in a memory BAR.
+    InvalidBar,
+    /// The MSI-X table could not be mapped.
+    MappingFailed,
+    /// The MSI-X table has no entry with the given index.
+    InvalidEntry(u16),
+    /// The MADT lists no enabled processor with the given APIC ID.
+    InvalidDestination(u8),
+}
+
+impl From<VectorError> for MsiError {
+    fn from(error: VectorError) -> Self {
+        MsiError::Vector(error)
+    }
+}
+
+/// Address and data a device writes to raise an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl MsiMessage {
+    /// Describes an edge triggered interrupt with fixed delivery of `vector` to
+    /// the local APIC with the given ID.
+    pub fn new(vector: u8, destination: u8) -> Self {
+        MsiMessage {
+            address: MESSAGE_ADDRESS_BASE | u64::from(destination) << 12,
+            data: u32::from(vector),
+        }
+    }
+}
+
+/// Allocates a vector for `handler` and lets the device raise it through its
+/// MSI capability, using a single message delivered to the local APIC with the
+/// APIC ID `destination`.
+///
+/// Legacy interrupts of the device are disabled. Returns the vector.
+pub fn enable_msi(device: &PciDevice, destination: u8, handler: VectorHandler) -> Result<u8, MsiError> {
+    let capability = u16::from(device.find_capability(CAPABILITY_MSI).ok_or(MsiError::NoCapability)?);
+    check_destination(destination)?;
+
+    let vector = interrupts::allocate_vector(handler)?;
+    let message = MsiMessage::new(vector, destination);
+
+    let control = device.read_u16(capability + MSI_CONTROL);
+    device.write_u32(capability + MSI_ADDRESS, message.address as u32);
+    let data_offset = if control & MSI_CONTROL_64BIT != 0 {
+        device.write_u32(capability + MSI_ADDRESS + 4, (message.address >> 32) as u32);
+        capability + 0x0c
+    } else {
+        capability + 0x08
+    };
+    device.write_u16(data_offset, message.data as u16);
+
+    let control = (control & !MSI_CONTROL_MULTIPLE_MESSAGE_ENABLE) | MSI_CONTROL_ENABLE;
+    device.write_u16(capability + MSI_CONTROL, control);
+    disable_legacy_interrupts(device);
+
+    Ok(vector)
+}
+
+/// Like `enable_msi`, delivering the interrupt to the current CPU.
+pub fn enable_msi_local(device: &PciDevice, handler: VectorHandler) -> Result<u8, MsiError> {
+    enable_msi(device, local_destination()?, handler)
+}
+
+/// Disables the MSI capability of the device.
+///
+/// The vector returned by `enable_msi` can be freed afterwards.
+pub fn disable_msi(device: &PciDevice) -> Result<(), MsiError> {
+    let capability = u16::from(device.find_capability(CAPABILITY_MSI).ok_or(MsiError::NoCapability)?);
+    let control = device.read_u16(capability + MSI_CONTROL);
+
+    device.write_u16(capability + MSI_CONTROL, control & !MSI_CONTROL_ENABLE);
+    Ok(())
+}
+
+fn local_destination() -> Result<u8, MsiError> {
+    apic::local_apic_id().ok_or(MsiError::ApicDisabled)
+}
+
+fn check_destination(destination: u8) -> Result<(), MsiError> {
+    if !apic::is_enabled() {
+        return Err(MsiError::ApicDisabled);
+    }
+    if !apic::is_processor(destination) {
+        return Err(MsiError::InvalidDestination(destination));
+    }
+
+    Ok(())
+}
+
+fn disable_legacy_interrupts(device: &PciDevice) {
+    device.set_command(device.command() | COMMAND_BUS_MASTER | COMMAND_INTERRUPT_DISABLE);
+}
+
+/// MSI-X capability of a device, with its mapped table of messages.
+#[derive(Debug)]
+pub struct MsiX {
+    device: PciAddress,
+    capability: u16,
+    table: VirtAddr,
+    table_size: u16,
+}
+
+impl MsiX {
+    /// Maps the MSI-X table of the device and enables MSI-X with all entries
+    /// masked.
+    ///
+    /// Legacy interrupts of the device are disabled.
+    pub fn new(device: &PciDevice) -> Result<Self, MsiError> {
+        let capability = device.find_capability(CAPABILITY_MSIX).ok_or(MsiError::NoCapability)?;
+        let capability = u16::from(capability);
+
+        let control = device.read_u16(capability + MSIX_CONTROL);
+        let table_size = (control & MSIX_CONTROL_TABLE_SIZE) + 1;
+
+        // the low bits select the BAR containing the table
+        let table_location = device.read_u32(capability + MSIX_TABLE);
+        let bar = device.bars.get((table_location & 0b111) as usize).copied().flatten();
+        let Some(Bar::Memory { address, .. }) = bar else {
+            return Err(MsiError::InvalidBar);
+        };
+
+        let table_address = PhysAddr::new(address + u64::from(table_location & !0b111));
+        let table = memory::map_mmio(table_address, u64::from(table_size) * MSIX_ENTRY_SIZE)
+            .map_err(|_| MsiError::MappingFailed)?;
+
+        let msix = MsiX {
+            device: device.address,
+            capability,
+            table,
+            table_size,
+        };
+
+        // mask the function while the entries are masked one by one
+        device.set_command(device.command() | COMMAND_MEMORY_SPACE);
+        device.write_u16(capability + MSIX_CONTROL, control | MSIX_CONTROL_FUNCTION_MASK | MSIX_CONTROL_ENABLE);
+        for entry in 0..table_size {
+            msix.set_masked(entry, true)?;
+        }
+        device.write_u16(capability + MSIX_CONTROL, (control | MSIX_CONTROL_ENABLE) & !MSIX_CONTROL_FUNCTION_MASK);
+        disable_legacy_interrupts(device);
+
+        Ok(msix)
+    }
+
+    /// Returns the number of entries in the MSI-X table.
+    pub fn table_size(&self) -> u16 {
+        self.table_size
+    }
+
+    /// Allocates a vector for `handler`, programs it into the given entry for
+    /// delivery to the local APIC with the APIC ID `destination` and unmasks
+    /// the entry. Returns the vector.
+    pub fn allocate(&self, entry: u16, destination: u8, handler: VectorHandler) -> Result<u8, MsiError> {
+        if entry >= self.table_size {
+            return Err(MsiError::InvalidEntry(entry));
+        }
+        check_destination(destination)?;
+
+        let vector = interrupts::allocate_vector(handler)?;
+        self.set_message(entry, MsiMessage::new(vector, destination))?;
+        self.set_masked(entry, false)?;
+
+        Ok(vector)
+    }
+
+    /// Like `allocate`, delivering the interrupt to the current CPU.
+    pub fn allocate_local(&self, entry: u16, handler: VectorHandler) -> Result<u8, MsiError> {
+        self.allocate(entry, local_destination()?, handler)
+    }
+
+    /// Writes the message of an entry, which should be masked.
+    pub fn set_message(&self, entry: u16, message: MsiMessage) -> Result<(), MsiError> {
+        let base = self.entry(entry)?;
+
+        unsafe {
+            ptr::write_volatile((base + MSIX_ENTRY_ADDRESS_LOW).as_mut_ptr::<u32>(), message.address as u32);
+            ptr::write_volatile((base + MSIX_ENTRY_ADDRESS_HIGH).as_mut_ptr::<u32>(), (message.address >> 32) as u32);
+            ptr::write_volatile((base + MSIX_ENTRY_DATA).as_mut_ptr::<u32>(), message.data);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_masked(&self, entry: u16, masked: bool) -> Result<(), MsiError> {
+        let control = (self.entry(entry)? + MSIX_ENTRY_VECTOR_CONTROL).as_mut_ptr::<u32>();
+
+        unsafe {
+            let value = ptr::read_volatile(control);
+            let value = if masked {
+                value | MSIX_ENTRY_MASKED
+            } else {
+                value & !MSIX_ENTRY_MASKED
+            };
+            ptr::write_volatile(control, value);
+        }
+
+        Ok(())
+    }
+
+    /// Disables MSI-X for the device.
+    pub fn disable(self) {
+        let control = config::read_u16(self.device, self.capability + MSIX_CONTROL);
+        config::write_u16(self.device, self.capability + MSIX_CONTROL, control & !MSIX_CONTROL_ENABLE);
+    }
+
+    fn entry(&self, entry: u16) -> Result<VirtAddr, MsiError> {
+        if entry >= self.table_size {
+            return Err(MsiError::InvalidEntry(entry));
+        }
+
+        Ok(self.table + u64::from(entry) * MSIX_ENTRY_SIZE)
+    }
+}
@@ -0,0 +1,368 @@
+use core::ptr;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{AcpiError, AddressSpace, GenericAddress};
+use crate::acpi::fadt::{self, Fadt};
+use crate::{memory, println, time};
+
+const PM1_CONTROL_SCI_ENABLE: u64 = 1 << 0;
+const PM1_CONTROL_SLEEP_TYPE_SHIFT: u64 = 10;
+const PM1_CONTROL_SLEEP_ENABLE: u64 = 1 << 13;
+/// Time the firmware gets to switch into ACPI mode.
+const ACPI_ENABLE_TIMEOUT_MS: u32 = 300;
+
+const KEYBOARD_CONTROLLER_STATUS: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_CONTROLLER_PULSE_RESET: u8 = 0xfe;
+
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_CHAR: u8 = b'\\';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    Acpi(AcpiError),
+    /// The DSDT does not define the `_S5` soft-off sleep state.
+    NoSoftOffState,
+    /// The FADT does not describe the required register.
+    MissingRegister,
+    /// The register is in an address space which is not supported.
+    UnsupportedRegister(AddressSpace),
+    /// The hardware did not react in time.
+    Timeout,
+}
+
+impl From<AcpiError> for PowerError {
+    fn from(error: AcpiError) -> Self {
+        PowerError::Acpi(error)
+    }
+}
+
+/// Turns the machine off by entering the ACPI S5 sleep state.
+///
+/// If that fails, the CPU is halted with interrupts disabled instead. Requires
+/// `memory::init`.
+pub fn shutdown() -> ! {
+    if let Err(error) = enter_soft_off() {
+        println!("Shutdown failed: {:?}", error);
+    }
+
+    println!("It is now safe to turn off the computer");
+    halt()
+}
+
+/// Restarts the machine.
+///
+/// Uses the ACPI reset register, falling back to a reset pulse of the 8042
+/// keyboard controller and finally to a triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+
+    if let Err(error) = acpi_reset() {
+        println!("ACPI reset failed: {:?}", error);
+    }
+
+    keyboard_controller_reset();
+
+    // an exception without a valid IDT escalates to a triple fault
+    unsafe {
+        let idt = DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        };
+        x86_64::instructions::tables::lidt(&idt);
+        x86_64::instructions::interrupts::int3();
+    }
+
+    halt()
+}
+
+fn halt() -> ! {
+    interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+fn enter_soft_off() -> Result<(), PowerError> {
+    let fadt = fadt::parse()?;
+    let dsdt = fadt.dsdt()?;
+    let (sleep_type_a, sleep_type_b) = find_soft_off_state(dsdt.data()).ok_or(PowerError::NoSoftOffState)?;
+
+    enable_acpi_mode(&fadt)?;
+
+    let pm1a_control = fadt.pm1a_control_block.ok_or(PowerError::MissingRegister)?;
+    let sleep = |block: GenericAddress, sleep_type: u8| -> Result<(), PowerError> {
+        let value = read_register(block, 16)?;
+        write_register(block, 16, sleep_control_value(value, sleep_type))
+    };
+
+    interrupts::disable();
+    sleep(pm1a_control, sleep_type_a)?;
+    if let Some(pm1b_control) = fadt.pm1b_control_block {
+        sleep(pm1b_control, sleep_type_b)?;
+    }
+
+    // give the hardware some time to power off
+    time::pit::wait_ms(100);
+    Err(PowerError::Timeout)
+}
+
+/// Returns the value of a PM1 control register which enters the sleep state of
+/// the given type, keeping the other bits of the `current` value.
+fn sleep_control_value(current: u64, sleep_type: u8) -> u64 {
+    (current & !(0b111 << PM1_CONTROL_SLEEP_TYPE_SHIFT))
+        | (u64::from(sleep_type & 0b111) << PM1_CONTROL_SLEEP_TYPE_SHIFT)
+        | PM1_CONTROL_SLEEP_ENABLE
+}
+
+/// Switches from legacy into ACPI mode, unless the system is in ACPI mode
+/// already.
+fn enable_acpi_mode(fadt: &Fadt) -> Result<(), PowerError> {
+    let pm1a_control = fadt.pm1a_control_block.ok_or(PowerError::MissingRegister)?;
+    let sci_enabled = || Ok::<_, PowerError>(read_register(pm1a_control, 16)? & PM1_CONTROL_SCI_ENABLE != 0);
+
+    if fadt.smi_command_port == 0 || fadt.acpi_enable == 0 || sci_enabled()? {
+        return Ok(());
+    }
+
+    unsafe { Port::new(fadt.smi_command_port as u16).write(fadt.acpi_enable) };
+
+    for _ in 0..ACPI_ENABLE_TIMEOUT_MS {
+        if sci_enabled()? {
+            return Ok(());
+        }
+        time::pit::wait_ms(1);
+    }
+
+    Err(PowerError::Timeout)
+}
+
+fn acpi_reset() -> Result<(), PowerError> {
+    let (register, value) = reset_write(&fadt::parse()?)?;
+    write_register(register, 8, value)?;
+
+    time::pit::wait_ms(100);
+    Err(PowerError::Timeout)
+}
+
+/// Returns the register and the value to write to it for resetting the
+/// machine, if the FADT declares the reset register as supported.
+fn reset_write(fadt: &Fadt) -> Result<(GenericAddress, u64), PowerError> {
+    if fadt.flags & fadt::FLAG_RESET_REGISTER_SUPPORTED == 0 {
+        return Err(PowerError::MissingRegister);
+    }
+
+    let register = fadt.reset_register.ok_or(PowerError::MissingRegister)?;
+    Ok((register, u64::from(fadt.reset_value)))
+}
+
+fn keyboard_controller_reset() {
+    let mut status: Port<u8> = Port::new(KEYBOARD_CONTROLLER_STATUS);
+
+    unsafe {
+        // wait until the controller accepts commands
+        for _ in 0..0x10000 {
+            if status.read() & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+                break;
+            }
+        }
+
+        status.write(KEYBOARD_CONTROLLER_PULSE_RESET);
+    }
+
+    time::pit::wait_ms(100);
+}
+
+/// Returns the bit width of the register, using `default_width` if the table
+/// does not specify it.
+fn register_width(register: GenericAddress, default_width: u8) -> u8 {
+    match register.bit_width {
+        0 => default_width,
+        width => width,
+    }
+}
+
+fn read_register(register: GenericAddress, default_width: u8) -> Result<u64, PowerError> {
+    let width = register_width(register, default_width);
+
+    match register.address_space {
+        AddressSpace::SystemIo => {
+            let port = register.address as u16;
+            Ok(unsafe {
+                match width {
+                    8 => u64::from(Port::<u8>::new(port).read()),
+                    16 => u64::from(Port::<u16>::new(port).read()),
+                    _ => u64::from(Port::<u32>::new(port).read()),
+                }
+            })
+        }
+        AddressSpace::SystemMemory => {
+            let addr = map_register(register)?;
+            Ok(unsafe {
+                match width {
+                    8 => u64::from(ptr::read_volatile(addr.as_ptr::<u8>())),
+                    16 => u64::from(ptr::read_volatile(addr.as_ptr::<u16>())),
+                    32 => u64::from(ptr::read_volatile(addr.as_ptr::<u32>())),
+                    _ => ptr::read_volatile(addr.as_ptr::<u64>()),
+                }
+            })
+        }
+        address_space => Err(PowerError::UnsupportedRegister(address_space)),
+    }
+}
+
+fn write_register(register: GenericAddress, default_width: u8, value: u64) -> Result<(), PowerError> {
+    let width = register_width(register, default_width);
+
+    match register.address_space {
+        AddressSpace::SystemIo => {
+            let port = register.address as u16;
+            unsafe {
+                match width {
+                    8 => Port::<u8>::new(port).write(value as u8),
+                    16 => Port::<u16>::new(port).write(value as u16),
+                    _ => Port::<u32>::new(port).write(value as u32),
+                }
+            }
+        }
+        AddressSpace::SystemMemory => {
+            let addr = map_register(register)?;
+            unsafe {
+                match width {
+                    8 => ptr::write_volatile(addr.as_mut_ptr::<u8>(), value as u8),
+                    16 => ptr::write_volatile(addr.as_mut_ptr::<u16>(), value as u16),
+                    32 => ptr::write_volatile(addr.as_mut_ptr::<u32>(), value as u32),
+                    _ => ptr::write_volatile(addr.as_mut_ptr::<u64>(), value),
+                }
+            }
+        }
+        address_space => return Err(PowerError::UnsupportedRegister(address_space)),
+    }
+
+    Ok(())
+}
+
+fn map_register(register: GenericAddress) -> Result<VirtAddr, PowerError> {
+    memory::map_mmio(PhysAddr::new(register.address), 8).map_err(|_| PowerError::Acpi(AcpiError::MappingFailed))
+}
+
+/// Finds the sleep types of the `_S5` package in the AML of the DSDT.
+///
+/// Only the simple form emitted by common firmware is understood, which is a
+/// `Name` defining a package of integer constants.
+fn find_soft_off_state(aml: &[u8]) -> Option<(u8, u8)> {
+    fn integer(bytes: &[u8]) -> Option<(u8, &[u8])> {
+        match *bytes.first()? {
+            AML_ZERO_OP => Some((0, &bytes[1..])),
+            AML_ONE_OP => Some((1, &bytes[1..])),
+            AML_BYTE_PREFIX => Some((*bytes.get(1)?, bytes.get(2..)?)),
+            _ => None,
+        }
+    }
+
+    aml.windows(4)
+        .enumerate()
+        .filter(|(_, window)| window == b"_S5_")
+        .find_map(|(position, _)| {
+            let before = &aml[..position];
+            if !before.ends_with(&[AML_NAME_OP]) && !before.ends_with(&[AML_NAME_OP, AML_ROOT_CHAR]) {
+                return None;
+            }
+
+            let package = aml.get(position + 4..)?;
+            if *package.first()? != AML_PACKAGE_OP {
+                return None;
+            }
+
+            // the top two bits of the package length count its following bytes,
+            // then comes the number of elements
+            let length_bytes = usize::from(*package.get(1)? >> 6) + 1;
+            let elements = package.get(1 + length_bytes + 1..)?;
+
+            let (sleep_type_a, rest) = integer(elements)?;
+            let sleep_type_b = integer(rest).map_or(0, |(value, _)| value);
+
+            Some((sleep_type_a, sleep_type_b))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fadt(flags: u32, reset_register: Option<GenericAddress>) -> Fadt {
+        Fadt {
+            firmware_ctrl: 0,
+            dsdt: 0,
+            preferred_pm_profile: 0,
+            sci_interrupt: 9,
+            smi_command_port: 0,
+            acpi_enable: 0,
+            acpi_disable: 0,
+            pm1a_event_block: None,
+            pm1b_event_block: None,
+            pm1a_control_block: None,
+            pm1b_control_block: None,
+            pm_timer_block: None,
+            pm1_event_length: 0,
+            pm1_control_length: 0,
+            pm_timer_length: 0,
+            century: 0,
+            iapc_boot_arch: 0,
+            flags,
+            reset_register,
+            reset_value: 0x06,
+        }
+    }
+
+    #[test_case]
+    fn test_find_soft_off_state() {
+        // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+        let aml = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0a, 0x05, 0x00, 0x00, 0x00];
+        assert_eq!(find_soft_off_state(&aml), Some((5, 0)));
+
+        let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0a, 0x07, 0x0a, 0x07, 0x00, 0x00];
+        assert_eq!(find_soft_off_state(&aml), Some((7, 7)));
+
+        assert_eq!(find_soft_off_state(b"_S5_"), None);
+    }
+
+    #[test_case]
+    fn test_sleep_control_value() {
+        // PM1a keeps SCI_EN and replaces the previous sleep type
+        let pm1a = sleep_control_value(PM1_CONTROL_SCI_ENABLE | 0b111 << PM1_CONTROL_SLEEP_TYPE_SHIFT, 5);
+        assert_eq!(pm1a, PM1_CONTROL_SCI_ENABLE | 5 << PM1_CONTROL_SLEEP_TYPE_SHIFT | PM1_CONTROL_SLEEP_ENABLE);
+
+        let pm1b = sleep_control_value(0, 0);
+        assert_eq!(pm1b, PM1_CONTROL_SLEEP_ENABLE);
+
+        // sleep types only have three bits
+        assert_eq!(sleep_control_value(0, 0xff), 0b111 << PM1_CONTROL_SLEEP_TYPE_SHIFT | PM1_CONTROL_SLEEP_ENABLE);
+    }
+
+    #[test_case]
+    fn test_reset_write() {
+        let register = GenericAddress {
+            address_space: AddressSpace::SystemIo,
+            bit_width: 8,
+            bit_offset: 0,
+            access_size: 1,
+            address: 0xcf9,
+        };
+
+        let supported = fadt(fadt::FLAG_RESET_REGISTER_SUPPORTED, Some(register));
+        assert_eq!(reset_write(&supported), Ok((register, 0x06)));
+
+        let unsupported = fadt(0, Some(register));
+        assert_eq!(reset_write(&unsupported), Err(PowerError::MissingRegister));
+
+        let missing = fadt(fadt::FLAG_RESET_REGISTER_SUPPORTED, None);
+        assert_eq!(reset_write(&missing), Err(PowerError::MissingRegister));
+    }
+}
@@ -0,0 +1,125 @@
+use core::{pin::Pin, task::{Context, Poll}};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use crate::interrupts::{self, InterruptIndex};
+use crate::{power, print, println};
+
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Number of scancodes dropped since the stream last reported them.
+static DROPPED_SCANCODES: AtomicUsize = AtomicUsize::new(0);
+
+/// Keyboard IRQ handler, which reads the scancode from the keyboard controller.
+fn handle_keyboard_irq(_irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    add_scancode(scancode);
+}
+
+/// Must not block or allocate, since it runs in interrupt context, so dropped
+/// scancodes are only counted and reported by the stream.
+fn add_scancode(scancode: u8) {
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) if queue.push(scancode).is_ok() => WAKER.wake(),
+        _ => {
+            DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    /// Creates the scancode stream.
+    ///
+    /// Only a single stream may exist, since it initializes the global scancode
+    /// queue and registers the keyboard IRQ handler on creation.
+    pub fn new() -> Self {
+        SCANCODE_QUEUE
+            .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE))
+            .expect("ScancodeStream::new should only be called once");
+
+        interrupts::register_irq(InterruptIndex::Keyboard.irq(), handle_keyboard_irq)
+            .expect("Registering the keyboard IRQ handler failed");
+
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE.try_get().expect("Scancode queue is not initialized");
+
+        let dropped = DROPPED_SCANCODES.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            println!("WARNING: dropped {} scancodes of keyboard input", dropped);
+        }
+
+        // fast path
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(context.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes scancodes from the keyboard interrupt and prints the typed keys.
+///
+/// Ctrl+Alt+Del reboots the machine.
+pub async fn print_keypresses() {
+    use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+
+    let mut scancodes = ScancodeStream::new();
+    let mut keyboard = Keyboard::new(ScancodeSet1::new(), layouts::Us104Key, HandleControl::Ignore);
+    let (mut control, mut alt) = (false, false);
+
+    while let Some(scancode) = scancodes.next().await {
+        let Ok(Some(key_event)) = keyboard.add_byte(scancode) else {
+            continue;
+        };
+
+        let pressed = key_event.state != KeyState::Up;
+        match key_event.code {
+            KeyCode::LControl | KeyCode::RControl => control = pressed,
+            KeyCode::LAlt | KeyCode::RAltGr => alt = pressed,
+            KeyCode::Delete if pressed && control && alt => power::reboot(),
+            _ => {}
+        }
+
+        if let Some(key) = keyboard.process_keyevent(key_event) {
+            match key {
+                DecodedKey::Unicode(character) => {
+                    print!("{}", character);
+                }
+                DecodedKey::RawKey(key) => {
+                    print!("{:?}", key);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+pub mod context;
+pub mod scheduler;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::{boxed::Box, vec};
+use x86_64::instructions::interrupts;
+use context::Context;
+use scheduler::SCHEDULER;
+
+/// Size of the heap-allocated stack given to every spawned thread.
+pub const THREAD_STACK_SIZE: usize = 4096 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// The thread which was running `kernel_main` before the scheduler took over.
+    pub const BOOT: ThreadId = ThreadId(0);
+
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    Runnable,
+    Finished,
+}
+
+/// Thread control block.
+pub struct Thread {
+    id: ThreadId,
+    state: ThreadState,
+    /// Stack pointer to the saved `Context` of the thread while it is not running.
+    context: u64,
+    /// Stack owned by the thread. The boot thread runs on the bootloader's stack
+    /// and thus has none.
+    // only accessed through `context`, the field keeps the allocation alive
+    #[allow(dead_code)]
+    stack: Option<Box<[u8]>>,
+}
+
+impl Thread {
+    const fn boot() -> Self {
+        Thread {
+            id: ThreadId::BOOT,
+            state: ThreadState::Runnable,
+            context: 0,
+            stack: None,
+        }
+    }
+
+    fn new(entry: fn()) -> Self {
+        let stack = vec![0u8; THREAD_STACK_SIZE].into_boxed_slice();
+        let stack_top = stack.as_ptr() as u64 + THREAD_STACK_SIZE as u64;
+        let context = unsafe { Context::write_initial(stack_top, thread_entry as *const () as u64, entry as *const () as u64) };
+
+        Thread {
+            id: ThreadId::new(),
+            state: ThreadState::Runnable,
+            context,
+            stack: Some(stack),
+        }
+    }
+}
+
+/// Spawns a new kernel thread which starts executing `entry` on the next
+/// scheduler tick.
+///
+/// The thread exits once `entry` returns.
+pub fn spawn(entry: fn()) -> ThreadId {
+    let thread = Thread::new(entry);
+    let id = thread.id;
+
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().add(thread);
+    });
+
+    id
+}
+
+/// Gives up the rest of the current time slice to the next runnable thread.
+pub fn yield_now() {
+    use crate::interrupts::YIELD_INTERRUPT;
+
+    unsafe {
+        core::arch::asm!("int {vector}", vector = const YIELD_INTERRUPT);
+    }
+}
+
+/// Terminates the current thread.
+///
+/// Its stack is freed once the scheduler has switched away from it.
+pub fn exit() -> ! {
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().finish_current();
+    });
+
+    // if there's no other thread to switch to, wait until one gets spawned
+    loop {
+        yield_now();
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Returns the ID of the currently running thread.
+pub fn current_thread_id() -> ThreadId {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current_id())
+}
+
+/// First code executed by every spawned thread. The address of the thread's
+/// entry function is passed in `rdi` by the initial context.
+extern "C" fn thread_entry(entry: u64) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry as usize) };
+    entry();
+    exit();
+}
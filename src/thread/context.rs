@@ -0,0 +1,135 @@
+use core::mem;
+
+/// Register state of an interrupted thread, laid out in the order in which it is
+/// found on the stack after `save_context!` has run.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Context {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    // pushed by the CPU on interrupt entry
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl Context {
+    /// Writes a context to the top of a fresh stack which, once restored, calls
+    /// `entry` with `arg` as its first argument and interrupts enabled.
+    ///
+    /// Returns the stack pointer which has to be passed to the restoring code.
+    ///
+    /// # Safety
+    ///
+    /// `stack_top` must be the end of a stack that is mapped writable and has
+    /// room for at least one `Context` plus 16 bytes of alignment below it. The
+    /// stack must not be used by anything else and must stay mapped until the
+    /// thread running on it has exited. `entry` must be the address of an
+    /// `extern "C"` function that never returns.
+    pub unsafe fn write_initial(stack_top: u64, entry: u64, arg: u64) -> u64 {
+        use x86_64::instructions::segmentation::{Segment, CS, SS};
+
+        const RFLAGS_INTERRUPTS_ENABLED: u64 = 0x202;
+
+        // entry function expects the stack to be misaligned by the return address
+        let entry_rsp = (stack_top & !0xf) - 8;
+        let context_addr = entry_rsp - mem::size_of::<Context>() as u64;
+
+        let context = Context {
+            r15: 0,
+            r14: 0,
+            r13: 0,
+            r12: 0,
+            r11: 0,
+            r10: 0,
+            r9: 0,
+            r8: 0,
+            rbp: 0,
+            rdi: arg,
+            rsi: 0,
+            rdx: 0,
+            rcx: 0,
+            rbx: 0,
+            rax: 0,
+            rip: entry,
+            cs: u64::from(CS::get_reg().0),
+            rflags: RFLAGS_INTERRUPTS_ENABLED,
+            rsp: entry_rsp,
+            ss: u64::from(SS::get_reg().0),
+        };
+
+        unsafe {
+            (entry_rsp as *mut u64).write(0);
+            (context_addr as *mut Context).write(context);
+        }
+
+        context_addr
+    }
+}
+
+/// Defines a naked interrupt entry point which saves the full register state of
+/// the interrupted thread on its stack, calls `$handler` with a pointer to that
+/// `Context` and resumes whichever context the handler returns.
+///
+/// The handler must have the signature `extern "C" fn(u64) -> u64`.
+macro_rules! context_switch_entry {
+    ($name:ident, $handler:path) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                "call {handler}",
+                "mov rsp, rax",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                handler = sym $handler,
+            );
+        }
+    };
+}
+
+pub(crate) use context_switch_entry;
@@ -0,0 +1,68 @@
+use alloc::collections::VecDeque;
+use spin::Mutex;
+use super::{Thread, ThreadId, ThreadState};
+
+/// Global round-robin scheduler.
+///
+/// It is locked from the timer interrupt, so code running in thread context
+/// must only lock it with interrupts disabled.
+pub(super) static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+
+pub struct Scheduler {
+    current: Thread,
+    run_queue: VecDeque<Thread>,
+    /// Thread which exited during the last switch. Its stack can only be freed
+    /// once we are no longer running on it.
+    finished: Option<Thread>,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Scheduler {
+            current: Thread::boot(),
+            run_queue: VecDeque::new(),
+            finished: None,
+        }
+    }
+
+    pub(super) fn add(&mut self, thread: Thread) {
+        self.run_queue.push_back(thread);
+    }
+
+    pub(super) fn current_id(&self) -> ThreadId {
+        self.current.id
+    }
+
+    pub(super) fn finish_current(&mut self) {
+        self.current.state = ThreadState::Finished;
+    }
+
+    /// Saves the context of the current thread and picks the next one to run.
+    ///
+    /// Returns the saved context of the thread to switch to, which is the same
+    /// as `context` if there is no other runnable thread.
+    fn switch(&mut self, context: u64) -> u64 {
+        self.finished.take();
+        self.current.context = context;
+
+        let next = match self.run_queue.pop_front() {
+            Some(next) => next,
+            None => return context,
+        };
+
+        let previous = core::mem::replace(&mut self.current, next);
+        match previous.state {
+            ThreadState::Runnable => self.run_queue.push_back(previous),
+            ThreadState::Finished => self.finished = Some(previous),
+        }
+
+        self.current.context
+    }
+}
+
+/// Entry point into the scheduler from the timer and yield interrupts.
+///
+/// Must be called with interrupts disabled.
+pub extern "C" fn schedule(context: u64) -> u64 {
+    SCHEDULER.lock().switch(context)
+}
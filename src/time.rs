@@ -0,0 +1,241 @@
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::apic;
+use crate::interrupts::IrqError;
+
+pub use core::time::Duration;
+
+/// Frequency of the timer interrupt set up by `init`.
+pub const DEFAULT_FREQUENCY_HZ: u32 = 100;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeError {
+    /// The timer can not generate interrupts at the given frequency.
+    UnsupportedFrequency(u32),
+    /// The handler of the timer IRQ could not be registered.
+    Irq(IrqError),
+}
+
+/// Number of timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Nanoseconds since boot, advanced on every tick.
+static NANOS: AtomicU64 = AtomicU64::new(0);
+static NANOS_PER_TICK: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY_HZ: AtomicU32 = AtomicU32::new(0);
+/// Frequency of the TSC, zero if the clock is based on timer ticks only.
+static TSC_FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+/// Value of the TSC when the clock was started.
+static TSC_AT_BOOT: AtomicU64 = AtomicU64::new(0);
+/// Whether the clock is based on the HPET main counter.
+static HPET_CLOCK: AtomicBool = AtomicBool::new(false);
+/// Nanoseconds since boot at which the HPET main counter was started.
+static HPET_START_NANOS: AtomicU64 = AtomicU64::new(0);
+/// UNIX timestamp of the moment the clock was started, read from the RTC.
+static BOOT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the PIT to raise the timer interrupt at `DEFAULT_FREQUENCY_HZ`,
+/// calibrates the TSC if it is invariant and reads the wall-clock time from the
+/// RTC.
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY_HZ).expect("default timer frequency is unsupported");
+
+    if tsc::is_invariant() && let Some(frequency_hz) = tsc::calibrate() {
+        TSC_AT_BOOT.store(tsc::read(), Ordering::Relaxed);
+        TSC_FREQUENCY_HZ.store(frequency_hz, Ordering::Relaxed);
+    }
+
+    let timestamp = rtc::read().to_unix_timestamp();
+    BOOT_TIMESTAMP.store(timestamp.saturating_sub(uptime().as_secs()), Ordering::Relaxed);
+}
+
+/// Changes the frequency of the timer interrupt, and thereby the resolution of
+/// the clock.
+///
+/// Programs the local APIC timer once it has replaced the PIT.
+pub fn set_frequency(frequency_hz: u32) -> Result<(), TimeError> {
+    if !(pit::MIN_FREQUENCY_HZ..=pit::BASE_FREQUENCY_HZ).contains(&frequency_hz) {
+        return Err(TimeError::UnsupportedFrequency(frequency_hz));
+    }
+
+    let period_nanos = if apic::is_enabled() {
+        apic::set_timer_frequency(frequency_hz);
+        NANOS_PER_SECOND / u64::from(frequency_hz)
+    } else {
+        let divisor = pit::set_frequency(frequency_hz);
+        u64::from(divisor) * NANOS_PER_SECOND / u64::from(pit::BASE_FREQUENCY_HZ)
+    };
+
+    NANOS_PER_TICK.store(period_nanos, Ordering::Relaxed);
+    FREQUENCY_HZ.store(frequency_hz, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the frequency of the timer interrupt.
+pub fn frequency() -> u32 {
+    FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+/// Advances the clock by one timer period.
+///
+/// Called from the timer interrupt handler.
+pub(crate) fn tick() {
+    NANOS.fetch_add(NANOS_PER_TICK.load(Ordering::Relaxed), Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Release);
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Acquire)
+}
+
+/// Switches the clock from the timer interrupt to the HPET main counter, which
+/// `hpet::init` has just started, unless it is measured with the TSC.
+pub(crate) fn start_hpet_clock() {
+    if TSC_FREQUENCY_HZ.load(Ordering::Relaxed) == 0 {
+        HPET_START_NANOS.store(NANOS.load(Ordering::Relaxed), Ordering::Relaxed);
+        HPET_CLOCK.store(true, Ordering::Release);
+    }
+}
+
+/// Returns the nanoseconds passed since the clock was started.
+///
+/// Measured with the TSC if it is invariant, with the HPET once it has been
+/// initialized, and with the resolution of the timer interrupt otherwise.
+pub fn nanos_since_boot() -> u64 {
+    let frequency_hz = TSC_FREQUENCY_HZ.load(Ordering::Relaxed);
+    if frequency_hz == 0 {
+        if HPET_CLOCK.load(Ordering::Acquire) && let Some(nanos) = hpet::nanos() {
+            return HPET_START_NANOS.load(Ordering::Relaxed) + nanos;
+        }
+        return NANOS.load(Ordering::Relaxed);
+    }
+
+    let cycles = tsc::read().saturating_sub(TSC_AT_BOOT.load(Ordering::Relaxed));
+    (u128::from(cycles) * u128::from(NANOS_PER_SECOND) / u128::from(frequency_hz)) as u64
+}
+
+/// Returns the time passed since the clock was started.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(nanos_since_boot())
+}
+
+/// Returns the current wall-clock time as a UNIX timestamp in seconds.
+///
+/// The time is read from the RTC once by `init` and then advanced by the
+/// monotonic clock.
+pub fn now() -> u64 {
+    BOOT_TIMESTAMP.load(Ordering::Relaxed) + uptime().as_secs()
+}
+
+/// Halts the CPU until at least `duration` has passed.
+///
+/// The CPU only wakes up from halting on interrupts, so with interrupts
+/// disabled this busy waits on the PIT instead.
+pub fn sleep(duration: Duration) {
+    if !x86_64::instructions::interrupts::are_enabled() {
+        let ms = duration.as_micros().div_ceil(1000);
+        pit::wait_ms(u32::try_from(ms).unwrap_or(u32::MAX));
+        return;
+    }
+
+    let deadline = Instant::now() + duration;
+
+    while Instant::now() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Point in time of the monotonic clock, measured since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant { nanos: nanos_since_boot() }
+    }
+
+    /// Returns the time passed since `earlier`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant { nanos: self.nanos.checked_add(nanos)? })
+    }
+
+    /// Returns the time passed since boot.
+    pub fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.nanos)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nanos_since_boot, sleep, ticks, uptime, Duration, Instant};
+
+    #[test_case]
+    fn test_sleep() {
+        let start = Instant::now();
+        let start_ticks = ticks();
+
+        sleep(Duration::from_millis(50));
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(ticks() > start_ticks);
+        assert!(uptime() >= start.since_boot());
+    }
+
+    #[test_case]
+    fn test_sleep_without_interrupts() {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            sleep(Duration::from_millis(10));
+        });
+    }
+
+    #[test_case]
+    fn test_nanos_since_boot_is_monotonic() {
+        let mut previous = nanos_since_boot();
+
+        for _ in 0..1000 {
+            let now = nanos_since_boot();
+            assert!(now >= previous);
+            previous = now;
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use core::ptr;
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{self, AcpiError};
+use crate::memory;
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIGURATION: usize = 0x010;
+const REG_MAIN_COUNTER: usize = 0x0f0;
+const REG_TIMER_BASE: usize = 0x100;
+const TIMER_STRIDE: usize = 0x20;
+const TIMER_CONFIGURATION: usize = 0x00;
+const TIMER_COMPARATOR: usize = 0x08;
+
+const CAPABILITIES_64BIT_COUNTER: u64 = 1 << 13;
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+const CONFIGURATION_LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_32BIT_MODE: u64 = 1 << 8;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1f << TIMER_ROUTE_SHIFT;
+const TIMER_FSB_ENABLE: u64 = 1 << 14;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+/// Size of the register block.
+const REGISTERS_SIZE: u64 = 0x400;
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    /// The HPET table could not be read.
+    Acpi(AcpiError),
+    /// The register block could not be mapped.
+    MappingFailed,
+    /// The HPET reports an invalid counter period.
+    InvalidPeriod,
+    /// The HPET was initialized already.
+    AlreadyInitialized,
+}
+
+impl From<AcpiError> for HpetError {
+    fn from(error: AcpiError) -> Self {
+        HpetError::Acpi(error)
+    }
+}
+
+/// Memory mapped registers of the High Precision Event Timer.
+#[derive(Debug)]
+pub struct Hpet {
+    base: VirtAddr,
+    /// Period of the main counter in femtoseconds.
+    period_fs: u64,
+    timer_count: u8,
+    counter_64bit: bool,
+}
+
+impl Hpet {
+    fn read(&self, register: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base + register).as_ptr::<u64>()) }
+    }
+
+    fn write(&self, register: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base + register).as_mut_ptr::<u64>(), value) }
+    }
+
+    fn timer_register(timer: u8, register: usize) -> usize {
+        REG_TIMER_BASE + usize::from(timer) * TIMER_STRIDE + register
+    }
+
+    /// Returns the value of the main counter.
+    pub fn counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// Returns the number of comparators, each of which is a timer.
+    pub fn timer_count(&self) -> u8 {
+        self.timer_count
+    }
+
+    pub fn counter_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    /// Converts a number of nanoseconds into main counter ticks.
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        (u128::from(nanos) * u128::from(FEMTOSECONDS_PER_NANOSECOND) / u128::from(self.period_fs)) as u64
+    }
+
+    /// Converts main counter ticks into nanoseconds.
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        (u128::from(ticks) * u128::from(self.period_fs) / u128::from(FEMTOSECONDS_PER_NANOSECOND)) as u64
+    }
+
+    /// Returns a bitmap of the I/O APIC inputs the given timer can be routed to.
+    pub fn routing_capabilities(&self, timer: u8) -> u32 {
+        (self.read(Self::timer_register(timer, TIMER_CONFIGURATION)) >> 32) as u32
+    }
+
+    /// Routes the interrupt of the given timer to an I/O APIC input, leaving the
+    /// timer disabled.
+    ///
+    /// The input has to be one of the `routing_capabilities` of the timer. The
+    /// interrupt is edge triggered.
+    pub fn route_timer(&self, timer: u8, gsi: u8) {
+        let register = Self::timer_register(timer, TIMER_CONFIGURATION);
+        let mut config = self.read(register);
+
+        config &= !(TIMER_ROUTE_MASK | TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC | TIMER_FSB_ENABLE | TIMER_32BIT_MODE);
+        config |= u64::from(gsi) << TIMER_ROUTE_SHIFT;
+
+        self.write(register, config);
+    }
+
+    /// Arms the given timer to raise its interrupt once the main counter reaches
+    /// `counter`.
+    pub fn set_one_shot(&self, timer: u8, counter: u64) {
+        let register = Self::timer_register(timer, TIMER_CONFIGURATION);
+
+        self.write(Self::timer_register(timer, TIMER_COMPARATOR), counter);
+        self.write(register, self.read(register) | TIMER_INTERRUPT_ENABLE);
+    }
+
+    /// Stops the given timer from raising interrupts.
+    pub fn disable_timer(&self, timer: u8) {
+        let register = Self::timer_register(timer, TIMER_CONFIGURATION);
+        self.write(register, self.read(register) & !TIMER_INTERRUPT_ENABLE);
+    }
+}
+
+/// Finds the HPET through the ACPI tables, maps its registers and starts the
+/// main counter.
+///
+/// Requires the heap and `memory::init`.
+pub fn init() -> Result<(), HpetError> {
+    if HPET.is_initialized() {
+        return Err(HpetError::AlreadyInitialized);
+    }
+
+    let table = acpi::hpet::parse()?;
+    let base = memory::map_mmio(PhysAddr::new(table.base_address), REGISTERS_SIZE)
+        .map_err(|_| HpetError::MappingFailed)?;
+
+    let mut hpet = Hpet {
+        base,
+        period_fs: 0,
+        timer_count: table.comparator_count,
+        counter_64bit: false,
+    };
+
+    let capabilities = hpet.read(REG_CAPABILITIES);
+    hpet.period_fs = capabilities >> 32;
+    hpet.timer_count = ((capabilities >> 8) & 0x1f) as u8 + 1;
+    hpet.counter_64bit = capabilities & CAPABILITIES_64BIT_COUNTER != 0;
+
+    // the period must not exceed 100 ns according to the specification
+    if hpet.period_fs == 0 || hpet.period_fs > 100 * FEMTOSECONDS_PER_NANOSECOND {
+        return Err(HpetError::InvalidPeriod);
+    }
+
+    for timer in 0..hpet.timer_count {
+        hpet.disable_timer(timer);
+    }
+
+    // the main counter can only be written while it is halted
+    let config = hpet.read(REG_CONFIGURATION) & !(CONFIGURATION_LEGACY_REPLACEMENT | CONFIGURATION_ENABLE);
+    hpet.write(REG_CONFIGURATION, config);
+    hpet.write(REG_MAIN_COUNTER, 0);
+    hpet.write(REG_CONFIGURATION, config | CONFIGURATION_ENABLE);
+
+    let counter_64bit = hpet.counter_64bit;
+    HPET.try_init_once(|| hpet).map_err(|_| HpetError::AlreadyInitialized)?;
+
+    // a 32-bit counter wraps within minutes
+    if counter_64bit {
+        super::start_hpet_clock();
+    }
+
+    Ok(())
+}
+
+/// Returns the HPET if `init` succeeded.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+/// Returns the nanoseconds counted by the HPET since `init`.
+pub fn nanos() -> Option<u64> {
+    get().map(|hpet| hpet.ticks_to_nanos(hpet.counter()))
+}
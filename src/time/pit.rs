@@ -0,0 +1,86 @@
+use x86_64::instructions::port::Port;
+use spin::Mutex;
+
+/// Frequency of the oscillator driving the PIT.
+pub const BASE_FREQUENCY_HZ: u32 = 1_193_182;
+/// Lowest frequency the PIT can generate, using the largest divisor.
+pub const MIN_FREQUENCY_HZ: u32 = BASE_FREQUENCY_HZ.div_ceil(1 << 16);
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Gate and output of channel 2, shared with the PC speaker.
+const CHANNEL_2_CONTROL: u16 = 0x61;
+
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+const MODE_INTERRUPT_ON_TERMINAL_COUNT: u8 = 0b000 << 1;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+const CHANNEL_2_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Programs channel 0, which raises IRQ 0, to fire at roughly the given frequency.
+///
+/// Returns the divisor in use, from which the exact period can be derived.
+/// Frequencies outside of the supported range are clamped.
+pub fn set_frequency(frequency_hz: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY_HZ / frequency_hz.max(1)).clamp(1, 1 << 16);
+
+    let _lock = LOCK.lock();
+    unsafe {
+        Port::new(COMMAND).write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_RATE_GENERATOR);
+        write_reload_value(Port::new(CHANNEL_0), divisor);
+    }
+
+    divisor
+}
+
+/// Busy waits for the given number of milliseconds using channel 2.
+///
+/// Works with interrupts disabled, so it can be used to calibrate other timers.
+pub fn wait_ms(ms: u32) {
+    // the largest count waits for about 54 ms
+    const MAX_STEP_MS: u32 = 50;
+
+    let mut remaining = ms;
+    while remaining > 0 {
+        let step = remaining.min(MAX_STEP_MS);
+        wait_counts(BASE_FREQUENCY_HZ * step / 1000);
+        remaining -= step;
+    }
+}
+
+fn wait_counts(count: u32) {
+    let mut control: Port<u8> = Port::new(CHANNEL_2_CONTROL);
+
+    let _lock = LOCK.lock();
+    unsafe {
+        // enable the gate of channel 2 while keeping the speaker off
+        let value = control.read();
+        control.write((value & !SPEAKER_ENABLE) | CHANNEL_2_GATE);
+
+        Port::new(COMMAND).write(SELECT_CHANNEL_2 | ACCESS_LOW_HIGH | MODE_INTERRUPT_ON_TERMINAL_COUNT);
+        write_reload_value(Port::new(CHANNEL_2), count);
+
+        while control.read() & CHANNEL_2_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        control.write(value);
+    }
+}
+
+/// Writes a reload value, where a value of 2^16 is encoded as zero.
+unsafe fn write_reload_value(mut port: Port<u8>, value: u32) {
+    let value = value as u16;
+
+    unsafe {
+        port.write(value as u8);
+        port.write((value >> 8) as u8);
+    }
+}
@@ -0,0 +1,225 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use spin::Mutex;
+use crate::interrupts;
+use super::TimeError;
+
+/// IRQ raised by the periodic interrupt of the RTC.
+pub const IRQ: u8 = 8;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const STATUS_C_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const HOURS_PM: u8 = 1 << 7;
+
+/// Frequency of the RTC oscillator, from which the periodic interrupt is derived.
+const BASE_FREQUENCY_HZ: u32 = 32_768;
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    index: Port::new(0x70),
+    data: Port::new(0x71),
+});
+
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+static HANDLER_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_raw(&mut self) -> [u8; 6] {
+        while self.update_in_progress() {
+            core::hint::spin_loop();
+        }
+
+        [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR].map(|register| self.read(register))
+    }
+}
+
+/// Calendar date and time of day in UTC, as kept by the RTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the number of seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix_timestamp(&self) -> u64 {
+        let days = days_since_epoch(i64::from(self.year), i64::from(self.month), i64::from(self.day));
+        let seconds = i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second);
+
+        (days * 86_400 + seconds).max(0) as u64
+    }
+}
+
+/// Returns the number of days between 1970-01-01 and the given date of the
+/// proleptic Gregorian calendar.
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    // count years from March, so that the leap day is the last day of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Reads the current date and time from the RTC.
+///
+/// The RTC only stores a two digit year, which is interpreted as a year
+/// between 1970 and 2069.
+pub fn read() -> DateTime {
+    let (raw, status_b) = without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        // read until two consecutive reads match, so no update happened in between
+        let mut raw = cmos.read_raw();
+        loop {
+            let again = cmos.read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+
+        (raw, cmos.read(REG_STATUS_B))
+    });
+
+    let [second, minute, hour, day, month, year] = raw;
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            (value >> 4) * 10 + (value & 0x0f)
+        }
+    };
+
+    let pm = hour & HOURS_PM != 0;
+    let mut hour = decode(hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let year = u16::from(decode(year));
+    DateTime {
+        year: if year < 70 { 2000 + year } else { 1900 + year },
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    }
+}
+
+/// Enables the periodic interrupt of the RTC on IRQ 8.
+///
+/// The frequency has to be a power of two between 2 and 8192 Hz.
+pub fn enable_periodic_interrupt(frequency_hz: u32) -> Result<(), TimeError> {
+    if !frequency_hz.is_power_of_two() || !(2..=8192).contains(&frequency_hz) {
+        return Err(TimeError::UnsupportedFrequency(frequency_hz));
+    }
+
+    // frequency = 32768 >> (rate - 1)
+    let rate = (BASE_FREQUENCY_HZ / frequency_hz).trailing_zeros() as u8 + 1;
+
+    if !HANDLER_REGISTERED.swap(true, Ordering::SeqCst)
+        && let Err(error) = interrupts::register_irq(IRQ, handle_rtc_irq)
+    {
+        // allow the next call to try again
+        HANDLER_REGISTERED.store(false, Ordering::SeqCst);
+        return Err(TimeError::Irq(error));
+    }
+
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        let status_a = cmos.read(REG_STATUS_A);
+        cmos.write(REG_STATUS_A, (status_a & 0xf0) | rate);
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+
+        // interrupts stop until status register C has been read
+        cmos.read(REG_STATUS_C);
+    });
+
+    Ok(())
+}
+
+/// Disables the periodic interrupt of the RTC.
+pub fn disable_periodic_interrupt() {
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+    });
+}
+
+/// Returns the number of periodic interrupts raised by the RTC.
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+fn handle_rtc_irq(_irq: u8) {
+    let status_c = CMOS.lock().read(REG_STATUS_C);
+
+    if status_c & STATUS_C_PERIODIC_INTERRUPT != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DateTime;
+
+    #[test_case]
+    fn test_unix_timestamp() {
+        let epoch = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+        assert_eq!(epoch.to_unix_timestamp(), 0);
+
+        let leap_day = DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 30, second: 15 };
+        assert_eq!(leap_day.to_unix_timestamp(), 1_709_209_815);
+    }
+}
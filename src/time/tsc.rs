@@ -0,0 +1,62 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use super::pit;
+
+const CPUID_EDX_TSC: u32 = 1 << 4;
+const CPUID_EDX_INVARIANT_TSC: u32 = 1 << 8;
+const LEAF_TSC_FREQUENCY: u32 = 0x15;
+const LEAF_ADVANCED_POWER_MANAGEMENT: u32 = 0x8000_0007;
+
+/// Duration of the calibration against the PIT.
+const CALIBRATION_MS: u32 = 10;
+
+/// Returns whether the CPU has a time stamp counter.
+pub fn is_supported() -> bool {
+    __cpuid(1).edx & CPUID_EDX_TSC != 0
+}
+
+/// Returns whether the TSC runs at a constant rate in all power states.
+pub fn is_invariant() -> bool {
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+
+    max_extended_leaf >= LEAF_ADVANCED_POWER_MANAGEMENT
+        && __cpuid(LEAF_ADVANCED_POWER_MANAGEMENT).edx & CPUID_EDX_INVARIANT_TSC != 0
+}
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Determines the frequency of the TSC in Hz.
+///
+/// Uses the frequency reported by CPUID if available and measures it against
+/// the PIT otherwise, which busy waits for a few milliseconds.
+pub fn calibrate() -> Option<u64> {
+    if !is_supported() {
+        return None;
+    }
+
+    frequency_from_cpuid().or_else(|| {
+        let start = read();
+        pit::wait_ms(CALIBRATION_MS);
+        let elapsed = read() - start;
+
+        Some(elapsed * 1000 / u64::from(CALIBRATION_MS))
+    })
+}
+
+/// Reads the TSC frequency from the ratio to the core crystal clock.
+fn frequency_from_cpuid() -> Option<u64> {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf < LEAF_TSC_FREQUENCY {
+        return None;
+    }
+
+    // eax / ebx is the ratio of the TSC to the crystal frequency in ecx
+    let leaf = __cpuid(LEAF_TSC_FREQUENCY);
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+
+    Some(u64::from(leaf.ecx) * u64::from(leaf.ebx) / u64::from(leaf.eax))
+}
@@ -0,0 +1,166 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::apic::{self, ApicError};
+use crate::interrupts::TIMER_EVENT_INTERRUPT;
+use crate::time::{hpet, Duration, Instant};
+
+/// HPET comparator used to raise an interrupt at the earliest deadline.
+const HPET_TIMER: u8 = 0;
+/// Minimum distance of a comparator to the main counter, so that the counter
+/// does not pass the comparator while it is being written.
+const MIN_HPET_DELAY_NANOS: u64 = 10_000;
+/// I/O APIC inputs which are reserved for ISA IRQs.
+const ISA_GSIS: u32 = 0xffff;
+
+/// Function run in interrupt context once the deadline of its event passed.
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Identifies a scheduled timer event, ordered by deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId {
+    deadline: Instant,
+    id: u64,
+}
+
+impl TimerId {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// The HPET has not been initialized.
+    NoHpet,
+    /// The HPET comparator can not be routed to a free I/O APIC input.
+    NoRoute,
+    Apic(ApicError),
+}
+
+impl From<ApicError> for TimerError {
+    fn from(error: ApicError) -> Self {
+        TimerError::Apic(error)
+    }
+}
+
+static EVENTS: Mutex<BTreeMap<TimerId, TimerCallback>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+/// Set once an HPET comparator raises an interrupt at the earliest deadline.
+static HPET_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// Uses an HPET comparator to run timer events at their exact deadline.
+///
+/// Without it, events run on the first timer interrupt after their deadline.
+/// Requires `apic::init` and `time::hpet::init`.
+pub fn init() -> Result<(), TimerError> {
+    let hpet = hpet::get().ok_or(TimerError::NoHpet)?;
+
+    let routes = hpet.routing_capabilities(HPET_TIMER) & !ISA_GSIS;
+    if routes == 0 {
+        return Err(TimerError::NoRoute);
+    }
+
+    let gsi = routes.trailing_zeros() as u8;
+    hpet.route_timer(HPET_TIMER, gsi);
+    apic::route_gsi(u32::from(gsi), TIMER_EVENT_INTERRUPT, false, false)?;
+
+    HPET_EVENTS.store(true, Ordering::SeqCst);
+    without_interrupts(rearm);
+
+    Ok(())
+}
+
+/// Runs `callback` once `deadline` has passed.
+///
+/// The callback runs in interrupt context, so it must not block. It may
+/// schedule further events.
+pub fn schedule_at(deadline: Instant, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    let id = TimerId {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    };
+
+    without_interrupts(|| {
+        let mut events = EVENTS.lock();
+        events.insert(id, Box::new(callback));
+        let earliest = events.keys().next() == Some(&id);
+        drop(events);
+
+        if earliest {
+            rearm();
+        }
+    });
+
+    id
+}
+
+/// Runs `callback` once `delay` has passed.
+pub fn schedule_after(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    schedule_at(Instant::now() + delay, callback)
+}
+
+/// Removes a scheduled event, returning false if it has already run.
+pub fn cancel(id: TimerId) -> bool {
+    without_interrupts(|| EVENTS.lock().remove(&id).is_some())
+}
+
+/// Runs expired events on the periodic timer interrupt.
+pub(crate) fn handle_tick() {
+    if run_expired() {
+        rearm();
+    }
+}
+
+/// Runs expired events on the interrupt of the HPET comparator.
+pub(crate) fn handle_interrupt() {
+    run_expired();
+    rearm();
+}
+
+/// Runs every event whose deadline has passed, returning whether there was any.
+fn run_expired() -> bool {
+    let now = Instant::now();
+    let mut ran = false;
+
+    loop {
+        // release the lock before running the callback, so that it can schedule events
+        let callback = match EVENTS.lock().first_entry() {
+            Some(entry) if entry.key().deadline <= now => entry.remove(),
+            _ => break,
+        };
+
+        callback();
+        ran = true;
+    }
+
+    ran
+}
+
+/// Programs the HPET comparator for the earliest deadline.
+///
+/// Must be called with interrupts disabled.
+fn rearm() {
+    if !HPET_EVENTS.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(hpet) = hpet::get() else {
+        return;
+    };
+
+    match EVENTS.lock().keys().next() {
+        Some(id) => {
+            let delay = id.deadline.duration_since(Instant::now()).as_nanos() as u64;
+            let ticks = hpet.nanos_to_ticks(delay.max(MIN_HPET_DELAY_NANOS));
+            let mut comparator = hpet.counter().wrapping_add(ticks);
+            // a 32-bit comparator matches on the low half of the counter only
+            if !hpet.counter_64bit() {
+                comparator &= u64::from(u32::MAX);
+            }
+            hpet.set_one_shot(HPET_TIMER, comparator);
+        }
+        None => hpet.disable_timer(HPET_TIMER),
+    }
+}
@@ -0,0 +1,344 @@
+pub mod file;
+pub mod path;
+pub mod tmpfs;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::block::BlockError;
+
+pub use file::{File, SeekFrom};
+
+pub const OPEN_READ: u32 = 1 << 0;
+pub const OPEN_WRITE: u32 = 1 << 1;
+/// Creates the file if it does not exist.
+pub const OPEN_CREATE: u32 = 1 << 2;
+/// Empties the file, requires `OPEN_WRITE`.
+pub const OPEN_TRUNCATE: u32 = 1 << 3;
+/// Moves to the end of the file before every write.
+pub const OPEN_APPEND: u32 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    /// The path is not absolute or names no entry.
+    InvalidPath,
+    /// The path is a mount point or has file systems mounted below it.
+    Busy,
+    /// The file was not opened for the operation.
+    PermissionDenied,
+    /// The position would be before the start of the file.
+    InvalidSeek,
+    /// The file system does not support the operation.
+    Unsupported,
+    /// The file system ran out of space.
+    NoSpace,
+    /// The paths are in different file systems.
+    CrossDevice,
+    Io(BlockError),
+}
+
+impl From<BlockError> for VfsError {
+    fn from(error: BlockError) -> Self {
+        VfsError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Number of the inode, unique within its file system.
+    pub inode: u64,
+    pub file_type: FileType,
+    /// Size in bytes, or the number of entries of a directory.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// File or directory of a file system.
+///
+/// The VFS checks the file type before an operation, so files only implement
+/// the file operations and directories only the directory operations.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Result<Metadata, VfsError>;
+
+    /// Reads from `offset`, returning the number of bytes read.
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, VfsError> {
+        Err(VfsError::IsADirectory)
+    }
+
+    /// Writes at `offset`, extending the file if necessary.
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, VfsError> {
+        Err(VfsError::IsADirectory)
+    }
+
+    /// Changes the size of the file, filling it with zeros when growing.
+    fn truncate(&self, _size: u64) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Returns the entry with the given name.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, VfsError> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Adds a new entry, failing if the name exists already.
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, VfsError> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Removes an entry, which must not be a directory with entries.
+    fn unlink(&self, _name: &str) -> Result<(), VfsError> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, VfsError> {
+        Err(VfsError::NotADirectory)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Returns a short name of the file system type.
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Moves an entry, replacing an existing file or empty directory at `to`.
+    ///
+    /// Both paths are normalized and relative to the root of the file system.
+    fn rename(&self, _from: &[&str], _to: &[&str]) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Writes all modified data to the underlying device.
+    fn sync(&self) -> Result<(), VfsError> {
+        Ok(())
+    }
+}
+
+struct Mount {
+    /// Components of the normalized mount point.
+    path: Vec<String>,
+    file_system: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Mounts `file_system` at the directory `path`.
+///
+/// The first file system has to be mounted at `/`. The previous contents of
+/// the directory are hidden until the file system is unmounted.
+pub fn mount(path: &str, file_system: Arc<dyn FileSystem>) -> Result<(), VfsError> {
+    let components = path::normalize(path)?;
+
+    if !components.is_empty() && resolve(&components)?.metadata()?.file_type != FileType::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+
+    without_interrupts(|| {
+        let mut mounts = MOUNTS.lock();
+        if mounts.iter().any(|mount| mount.path == components) {
+            return Err(VfsError::Busy);
+        }
+
+        mounts.push(Mount {
+            path: components.iter().map(|component| component.to_string()).collect(),
+            file_system,
+        });
+        Ok(())
+    })
+}
+
+/// Syncs and removes the file system mounted at `path`.
+pub fn unmount(path: &str) -> Result<(), VfsError> {
+    let components = path::normalize(path)?;
+
+    let file_system = without_interrupts(|| {
+        let mut mounts = MOUNTS.lock();
+        let index = mounts.iter().position(|mount| mount.path == components).ok_or(VfsError::InvalidPath)?;
+
+        let nested = mounts
+            .iter()
+            .any(|mount| mount.path.len() > components.len() && is_prefix(&components, &mount.path));
+        if nested {
+            return Err(VfsError::Busy);
+        }
+
+        Ok(mounts.remove(index).file_system)
+    })?;
+
+    file_system.sync()
+}
+
+/// Returns the mount points with the name of their file system.
+pub fn mounts() -> Vec<(String, &'static str)> {
+    without_interrupts(|| {
+        MOUNTS
+            .lock()
+            .iter()
+            .map(|mount| (String::from("/") + &mount.path.join("/"), mount.file_system.name()))
+            .collect()
+    })
+}
+
+/// Writes the modified data of all mounted file systems to their devices.
+pub fn sync() -> Result<(), VfsError> {
+    let file_systems: Vec<_> =
+        without_interrupts(|| MOUNTS.lock().iter().map(|mount| mount.file_system.clone()).collect());
+
+    file_systems.iter().try_for_each(|file_system| file_system.sync())
+}
+
+/// Returns whether `path` starts with the components of `prefix`.
+fn is_prefix(prefix: &[impl AsRef<str>], path: &[impl AsRef<str>]) -> bool {
+    prefix.len() <= path.len() && prefix.iter().zip(path).all(|(a, b)| a.as_ref() == b.as_ref())
+}
+
+/// Returns whether a file system is mounted exactly at the path.
+fn is_mount_point(components: &[&str]) -> bool {
+    without_interrupts(|| MOUNTS.lock().iter().any(|mount| mount.path == components))
+}
+
+/// Returns the file system mounted at the longest prefix of the normalized
+/// path, and the length of that prefix.
+fn find_mount(components: &[&str]) -> Result<(Arc<dyn FileSystem>, usize), VfsError> {
+    without_interrupts(|| {
+        MOUNTS
+            .lock()
+            .iter()
+            .filter(|mount| is_prefix(&mount.path, components))
+            .max_by_key(|mount| mount.path.len())
+            .map(|mount| (mount.file_system.clone(), mount.path.len()))
+    })
+    .ok_or(VfsError::NotFound)
+}
+
+/// Returns the inode at the normalized path, entering the file system mounted
+/// at the longest matching prefix.
+fn resolve(components: &[&str]) -> Result<Arc<dyn Inode>, VfsError> {
+    let (file_system, depth) = find_mount(components)?;
+
+    let mut inode = file_system.root();
+    for name in &components[depth..] {
+        if inode.metadata()?.file_type != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        inode = inode.lookup(name)?;
+    }
+
+    Ok(inode)
+}
+
+fn resolve_path(path: &str) -> Result<Arc<dyn Inode>, VfsError> {
+    resolve(&path::normalize(path)?)
+}
+
+fn create(path: &str, file_type: FileType) -> Result<Arc<dyn Inode>, VfsError> {
+    let (parent, name) = path::split_parent(path)?;
+
+    let mut components = parent.clone();
+    components.push(name);
+    if is_mount_point(&components) {
+        return Err(VfsError::AlreadyExists);
+    }
+
+    let parent = resolve(&parent)?;
+    if parent.metadata()?.file_type != FileType::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+    parent.create(name, file_type)
+}
+
+/// Opens the file at `path` with a combination of the `OPEN_*` flags.
+pub fn open(path: &str, flags: u32) -> Result<Box<dyn File>, VfsError> {
+    let inode = match resolve_path(path) {
+        Err(VfsError::NotFound) if flags & OPEN_CREATE != 0 => create(path, FileType::File)?,
+        inode => inode?,
+    };
+
+    if inode.metadata()?.file_type == FileType::Directory && flags & (OPEN_WRITE | OPEN_TRUNCATE) != 0 {
+        return Err(VfsError::IsADirectory);
+    }
+    if flags & OPEN_TRUNCATE != 0 {
+        if flags & OPEN_WRITE == 0 {
+            return Err(VfsError::PermissionDenied);
+        }
+        inode.truncate(0)?;
+    }
+
+    Ok(Box::new(file::InodeFile::new(inode, flags)))
+}
+
+pub fn stat(path: &str) -> Result<Metadata, VfsError> {
+    resolve_path(path)?.metadata()
+}
+
+/// Lists the entries of the directory at `path`, without `.` and `..`.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, VfsError> {
+    let inode = resolve_path(path)?;
+    if inode.metadata()?.file_type != FileType::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+
+    inode.read_dir()
+}
+
+pub fn mkdir(path: &str) -> Result<(), VfsError> {
+    create(path, FileType::Directory).map(|_| ())
+}
+
+/// Removes the file or empty directory at `path`.
+pub fn unlink(path: &str) -> Result<(), VfsError> {
+    let (parent, name) = path::split_parent(path)?;
+
+    let mut components = parent.clone();
+    components.push(name);
+    let nested = without_interrupts(|| MOUNTS.lock().iter().any(|mount| is_prefix(&components, &mount.path)));
+    if nested {
+        return Err(VfsError::Busy);
+    }
+
+    resolve(&parent)?.unlink(name)
+}
+
+/// Moves the entry at `from` to `to` within a single file system.
+pub fn rename(from: &str, to: &str) -> Result<(), VfsError> {
+    let from = path::normalize(from)?;
+    let to = path::normalize(to)?;
+    if from.is_empty() || to.is_empty() {
+        return Err(VfsError::InvalidPath);
+    }
+
+    let busy = without_interrupts(|| {
+        MOUNTS.lock().iter().any(|mount| is_prefix(&from, &mount.path) || mount.path == to)
+    });
+    if busy {
+        return Err(VfsError::Busy);
+    }
+
+    let (file_system, depth) = find_mount(&from)?;
+    let (to_file_system, to_depth) = find_mount(&to)?;
+    if !Arc::ptr_eq(&file_system, &to_file_system) || depth != to_depth {
+        return Err(VfsError::CrossDevice);
+    }
+
+    file_system.rename(&from[depth..], &to[depth..])
+}
@@ -0,0 +1,93 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use super::{DirEntry, FileType, Inode, Metadata, VfsError, OPEN_APPEND, OPEN_READ, OPEN_WRITE};
+
+/// Position given to `File::seek`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// Open file with a position, returned by `vfs::open`.
+pub trait File: Send {
+    /// Reads from the current position, returning the number of bytes read,
+    /// which is 0 at the end of the file.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError>;
+
+    /// Writes at the current position, returning the number of bytes written.
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, VfsError>;
+
+    /// Moves the position, returning the new position from the start.
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError>;
+
+    fn stat(&self) -> Result<Metadata, VfsError>;
+
+    /// Lists the entries if the file is a directory.
+    fn read_dir(&self) -> Result<Vec<DirEntry>, VfsError>;
+}
+
+/// File which reads and writes an inode.
+pub(super) struct InodeFile {
+    inode: Arc<dyn Inode>,
+    position: u64,
+    flags: u32,
+}
+
+impl InodeFile {
+    pub(super) fn new(inode: Arc<dyn Inode>, flags: u32) -> Self {
+        InodeFile {
+            inode,
+            position: 0,
+            flags,
+        }
+    }
+}
+
+impl File for InodeFile {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        if self.flags & OPEN_READ == 0 {
+            return Err(VfsError::PermissionDenied);
+        }
+
+        let read = self.inode.read_at(self.position, buffer)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, VfsError> {
+        if self.flags & OPEN_WRITE == 0 {
+            return Err(VfsError::PermissionDenied);
+        }
+        if self.flags & OPEN_APPEND != 0 {
+            self.position = self.inode.metadata()?.size;
+        }
+
+        let written = self.inode.write_at(self.position, buffer)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError> {
+        let (base, offset) = match position {
+            SeekFrom::Start(position) => (position, 0),
+            SeekFrom::End(offset) => (self.inode.metadata()?.size, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        self.position = base.checked_add_signed(offset).ok_or(VfsError::InvalidSeek)?;
+        Ok(self.position)
+    }
+
+    fn stat(&self) -> Result<Metadata, VfsError> {
+        self.inode.metadata()
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, VfsError> {
+        match self.inode.metadata()?.file_type {
+            FileType::Directory => self.inode.read_dir(),
+            FileType::File => Err(VfsError::NotADirectory),
+        }
+    }
+}
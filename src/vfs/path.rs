@@ -0,0 +1,34 @@
+use alloc::vec::Vec;
+use super::VfsError;
+
+/// Splits an absolute path into its components, resolving `.` and `..`.
+///
+/// `..` in the root directory refers to the root directory itself. Since there
+/// are no symbolic links, this gives the same result as resolving the
+/// components one by one.
+pub fn normalize(path: &str) -> Result<Vec<&str>, VfsError> {
+    if !path.starts_with('/') {
+        return Err(VfsError::InvalidPath);
+    }
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+
+    Ok(components)
+}
+
+/// Splits an absolute path into the components of its parent directory and
+/// its last component.
+pub fn split_parent(path: &str) -> Result<(Vec<&str>, &str), VfsError> {
+    let mut components = normalize(path)?;
+    let name = components.pop().ok_or(VfsError::InvalidPath)?;
+    Ok((components, name))
+}
@@ -0,0 +1,331 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use super::{DirEntry, FileSystem, FileType, Inode, Metadata, VfsError};
+
+type Entries = BTreeMap<String, Arc<Node>>;
+
+/// Space accounting shared by all nodes of a file system.
+#[derive(Debug)]
+struct Usage {
+    used: AtomicUsize,
+    limit: usize,
+    next_inode: AtomicU64,
+}
+
+impl Usage {
+    fn reserve(&self, bytes: usize) -> Result<(), VfsError> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|_| VfsError::NoSpace)
+    }
+
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+enum Content {
+    File(Mutex<Vec<u8>>),
+    Directory(Mutex<Entries>),
+}
+
+#[derive(Debug)]
+struct Node {
+    inode: u64,
+    usage: Arc<Usage>,
+    content: Content,
+}
+
+impl Node {
+    fn new(usage: &Arc<Usage>, file_type: FileType) -> Arc<Self> {
+        let content = match file_type {
+            FileType::File => Content::File(Mutex::new(Vec::new())),
+            FileType::Directory => Content::Directory(Mutex::new(BTreeMap::new())),
+        };
+
+        Arc::new(Node {
+            inode: usage.next_inode.fetch_add(1, Ordering::Relaxed),
+            usage: usage.clone(),
+            content,
+        })
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.content {
+            Content::File(_) => FileType::File,
+            Content::Directory(_) => FileType::Directory,
+        }
+    }
+
+    fn data(&self) -> Result<MutexGuard<'_, Vec<u8>>, VfsError> {
+        match &self.content {
+            Content::File(data) => Ok(data.lock()),
+            Content::Directory(_) => Err(VfsError::IsADirectory),
+        }
+    }
+
+    fn entries(&self) -> Result<MutexGuard<'_, Entries>, VfsError> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries.lock()),
+            Content::File(_) => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn child(&self, name: &str) -> Result<Arc<Node>, VfsError> {
+        self.entries()?.get(name).cloned().ok_or(VfsError::NotFound)
+    }
+
+    /// Resizes the file, reserving or releasing the difference.
+    fn resize(&self, data: &mut Vec<u8>, size: usize) -> Result<(), VfsError> {
+        if size > data.len() {
+            self.usage.reserve(size - data.len())?;
+        } else {
+            self.usage.release(data.len() - size);
+        }
+
+        data.resize(size, 0);
+        Ok(())
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // the data of an unlinked file is freed once it is no longer open
+        if let Content::File(data) = &self.content {
+            self.usage.release(data.lock().len());
+        }
+    }
+}
+
+impl Inode for Node {
+    fn metadata(&self) -> Result<Metadata, VfsError> {
+        let size = match &self.content {
+            Content::File(data) => data.lock().len(),
+            Content::Directory(entries) => entries.lock().len(),
+        };
+
+        Ok(Metadata {
+            inode: self.inode,
+            file_type: self.file_type(),
+            size: size as u64,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        let data = self.data()?;
+        let Some(available) = usize::try_from(offset).ok().and_then(|offset| data.get(offset..)) else {
+            return Ok(0);
+        };
+
+        let length = buffer.len().min(available.len());
+        buffer[..length].copy_from_slice(&available[..length]);
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, VfsError> {
+        let mut data = self.data()?;
+        let offset = usize::try_from(offset).map_err(|_| VfsError::NoSpace)?;
+        let end = offset.checked_add(buffer.len()).ok_or(VfsError::NoSpace)?;
+
+        if end > data.len() {
+            self.resize(&mut data, end)?;
+        }
+        data[offset..end].copy_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), VfsError> {
+        let mut data = self.data()?;
+        let size = usize::try_from(size).map_err(|_| VfsError::NoSpace)?;
+
+        self.resize(&mut data, size)?;
+        data.shrink_to_fit();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, VfsError> {
+        Ok(self.child(name)?)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, VfsError> {
+        check_name(name)?;
+
+        let mut entries = self.entries()?;
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let node = Node::new(&self.usage, file_type);
+        entries.insert(name.to_string(), node.clone());
+        Ok(node)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), VfsError> {
+        let mut entries = self.entries()?;
+        let node = entries.get(name).ok_or(VfsError::NotFound)?;
+
+        if let Content::Directory(children) = &node.content
+            && !children.lock().is_empty()
+        {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, VfsError> {
+        Ok(self
+            .entries()?
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                inode: node.inode,
+                file_type: node.file_type(),
+            })
+            .collect())
+    }
+}
+
+fn check_name(name: &str) -> Result<(), VfsError> {
+    match name {
+        "" | "." | ".." => Err(VfsError::InvalidPath),
+        name if name.contains('/') => Err(VfsError::InvalidPath),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `node` may replace the existing entry `target`.
+fn check_replace(node: &Node, target: Option<&Arc<Node>>) -> Result<(), VfsError> {
+    let Some(target) = target else {
+        return Ok(());
+    };
+
+    match (node.file_type(), &target.content) {
+        (FileType::File, Content::File(_)) => Ok(()),
+        (FileType::Directory, Content::Directory(entries)) if entries.lock().is_empty() => Ok(()),
+        (FileType::Directory, Content::Directory(_)) => Err(VfsError::DirectoryNotEmpty),
+        (FileType::File, Content::Directory(_)) => Err(VfsError::IsADirectory),
+        (FileType::Directory, Content::File(_)) => Err(VfsError::NotADirectory),
+    }
+}
+
+/// File system keeping files and directories in heap memory.
+///
+/// The size of the file contents is limited, directories are not counted.
+///
+/// When the entries of two directories are locked at once, the parent is
+/// always locked before its child, or generally an ancestor before its
+/// descendant. Apart from renames this only happens for a directory and one
+/// of its entries. Renames are serialised by `rename_lock`, so that the paths
+/// do not change while they work out which directory to lock first, and two
+/// unrelated directories are only ever locked together by the one rename.
+#[derive(Debug)]
+pub struct Tmpfs {
+    root: Arc<Node>,
+    usage: Arc<Usage>,
+    rename_lock: Mutex<()>,
+}
+
+impl Tmpfs {
+    /// Creates an empty file system holding up to `limit` bytes of file data.
+    pub fn new(limit: usize) -> Self {
+        let usage = Arc::new(Usage {
+            used: AtomicUsize::new(0),
+            limit,
+            next_inode: AtomicU64::new(1),
+        });
+
+        Tmpfs {
+            root: Node::new(&usage, FileType::Directory),
+            usage,
+            rename_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the number of bytes used by file data.
+    pub fn used(&self) -> usize {
+        self.usage.used.load(Ordering::SeqCst)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.usage.limit
+    }
+
+    /// Returns the directory at the path relative to the root.
+    fn directory(&self, components: &[&str]) -> Result<Arc<Node>, VfsError> {
+        let mut node = self.root.clone();
+        for name in components {
+            node = node.child(name)?;
+        }
+
+        node.entries()?;
+        Ok(node)
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn rename(&self, from: &[&str], to: &[&str]) -> Result<(), VfsError> {
+        let (from_name, from_parent) = from.split_last().ok_or(VfsError::InvalidPath)?;
+        let (to_name, to_parent) = to.split_last().ok_or(VfsError::InvalidPath)?;
+        check_name(to_name)?;
+
+        if from == to {
+            return Ok(());
+        }
+        // a directory can not be moved into itself
+        if to.starts_with(from) {
+            return Err(VfsError::InvalidPath);
+        }
+        // nor replace one of its ancestors, which are not empty
+        if from.starts_with(to) {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+
+        let _rename = self.rename_lock.lock();
+        let source = self.directory(from_parent)?;
+        let target = self.directory(to_parent)?;
+
+        if Arc::ptr_eq(&source, &target) {
+            let mut entries = source.entries()?;
+            let node = entries.get(*from_name).cloned().ok_or(VfsError::NotFound)?;
+            check_replace(&node, entries.get(*to_name))?;
+
+            entries.remove(*from_name);
+            entries.insert(to_name.to_string(), node);
+            return Ok(());
+        }
+
+        // lock the ancestor first if one directory contains the other
+        let (mut source_entries, mut target_entries) = if to_parent.starts_with(from_parent) {
+            let source_entries = source.entries()?;
+            (source_entries, target.entries()?)
+        } else {
+            let target_entries = target.entries()?;
+            (source.entries()?, target_entries)
+        };
+
+        let node = source_entries.get(*from_name).cloned().ok_or(VfsError::NotFound)?;
+        check_replace(&node, target_entries.get(*to_name))?;
+
+        source_entries.remove(*from_name);
+        target_entries.insert(to_name.to_string(), node);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+pub mod queue;
+
+use x86_64::instructions::port::{Port, PortRead, PortWrite};
+use crate::apic::ApicError;
+use crate::interrupts::IrqError;
+use crate::pci::msi::MsiError;
+use crate::pci::{Bar, PciDevice};
+
+pub use queue::{Buffer, Virtqueue};
+
+pub const VENDOR_ID: u16 = 0x1af4;
+/// Device ID of transitional block devices, which have the legacy interface.
+pub const DEVICE_ID_BLOCK_LEGACY: u16 = 0x1001;
+
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_ADDRESS: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0c;
+const REG_QUEUE_SELECT: u16 = 0x0e;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_DEVICE_STATUS: u16 = 0x12;
+const REG_ISR_STATUS: u16 = 0x13;
+const REG_CONFIG_VECTOR: u16 = 0x14;
+const REG_QUEUE_VECTOR: u16 = 0x16;
+
+/// Offset of the device specific configuration, which moves behind the vector
+/// registers once MSI-X is enabled.
+const DEVICE_CONFIG: u16 = 0x14;
+const DEVICE_CONFIG_MSIX: u16 = 0x18;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+pub const STATUS_DRIVER: u8 = 1 << 1;
+pub const STATUS_DRIVER_OK: u8 = 1 << 2;
+pub const STATUS_FAILED: u8 = 1 << 7;
+
+pub const ISR_QUEUE: u8 = 1 << 0;
+
+/// MSI-X vector register value which disables the interrupt.
+pub const NO_VECTOR: u16 = 0xffff;
+/// Legacy queues are given to the device as a page frame number.
+const QUEUE_ADDRESS_SHIFT: u64 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device has no I/O BAR 0 for the legacy interface.
+    NoIoBar,
+    /// The device does not have the queue.
+    QueueUnavailable(u16),
+    InvalidQueueSize(u16),
+    /// The frames for a queue or buffer could not be allocated.
+    OutOfMemory,
+    /// The device did not accept the MSI-X vector.
+    VectorRejected,
+    Msi(MsiError),
+    Irq(IrqError),
+    Apic(ApicError),
+}
+
+impl From<MsiError> for VirtioError {
+    fn from(error: MsiError) -> Self {
+        VirtioError::Msi(error)
+    }
+}
+
+impl From<IrqError> for VirtioError {
+    fn from(error: IrqError) -> Self {
+        VirtioError::Irq(error)
+    }
+}
+
+impl From<ApicError> for VirtioError {
+    fn from(error: ApicError) -> Self {
+        VirtioError::Apic(error)
+    }
+}
+
+/// Registers of the legacy virtio-pci interface in I/O BAR 0.
+#[derive(Debug, Clone, Copy)]
+pub struct LegacyTransport {
+    io_base: u16,
+    msix: bool,
+}
+
+impl LegacyTransport {
+    /// Enables decoding and bus mastering of the device and resets it.
+    pub fn new(device: &PciDevice) -> Result<Self, VirtioError> {
+        let Some(Bar::Io { port, .. }) = device.bars[0] else {
+            return Err(VirtioError::NoIoBar);
+        };
+
+        device.enable_bus_mastering();
+
+        let transport = LegacyTransport {
+            io_base: port as u16,
+            msix: false,
+        };
+        transport.reset();
+
+        Ok(transport)
+    }
+
+    fn read<T: PortRead>(&self, register: u16) -> T {
+        unsafe { Port::new(self.io_base + register).read() }
+    }
+
+    fn write<T: PortWrite>(&self, register: u16, value: T) {
+        unsafe { Port::new(self.io_base + register).write(value) }
+    }
+
+    /// Resets the device, which stops it from accessing its queues.
+    pub fn reset(&self) {
+        self.write(REG_DEVICE_STATUS, 0u8);
+    }
+
+    pub fn status(&self) -> u8 {
+        self.read(REG_DEVICE_STATUS)
+    }
+
+    /// Sets additional bits of the device status.
+    pub fn add_status(&self, status: u8) {
+        self.write(REG_DEVICE_STATUS, self.status() | status);
+    }
+
+    /// Returns the features offered by the device.
+    pub fn device_features(&self) -> u32 {
+        self.read(REG_DEVICE_FEATURES)
+    }
+
+    /// Tells the device which of its features the driver uses.
+    pub fn set_guest_features(&self, features: u32) {
+        self.write(REG_GUEST_FEATURES, features);
+    }
+
+    /// Returns the size the device requires for the given queue, 0 if the queue
+    /// does not exist.
+    pub fn queue_size(&self, queue: u16) -> u16 {
+        self.write(REG_QUEUE_SELECT, queue);
+        self.read(REG_QUEUE_SIZE)
+    }
+
+    /// Gives a queue of the size returned by `queue_size` to the device.
+    pub fn set_queue(&self, queue: u16, virtqueue: &Virtqueue) {
+        self.write(REG_QUEUE_SELECT, queue);
+        self.write(REG_QUEUE_ADDRESS, (virtqueue.physical_address().as_u64() >> QUEUE_ADDRESS_SHIFT) as u32);
+    }
+
+    /// Tells the device that new buffers are available in the given queue.
+    pub fn notify(&self, queue: u16) {
+        self.write(REG_QUEUE_NOTIFY, queue);
+    }
+
+    /// Reads the interrupt status, which acknowledges a legacy interrupt.
+    pub fn read_isr(&self) -> u8 {
+        self.read(REG_ISR_STATUS)
+    }
+
+    /// Assigns MSI-X table entries to configuration changes and to a queue.
+    ///
+    /// MSI-X has to be enabled on the device before, which moves the device
+    /// configuration. If the device rejects the entries, MSI-X has to be
+    /// disabled again.
+    pub fn set_msix_vectors(&mut self, config: u16, queue: u16, queue_entry: u16) -> Result<(), VirtioError> {
+        self.msix = true;
+
+        self.write(REG_CONFIG_VECTOR, config);
+        self.write(REG_QUEUE_SELECT, queue);
+        self.write(REG_QUEUE_VECTOR, queue_entry);
+
+        // the device answers with NO_VECTOR if it could not allocate the entry
+        if self.read::<u16>(REG_QUEUE_VECTOR) != queue_entry || self.read::<u16>(REG_CONFIG_VECTOR) != config {
+            self.msix = false;
+            return Err(VirtioError::VectorRejected);
+        }
+
+        Ok(())
+    }
+
+    fn device_config(&self) -> u16 {
+        if self.msix { DEVICE_CONFIG_MSIX } else { DEVICE_CONFIG }
+    }
+
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        self.read(self.device_config() + offset)
+    }
+
+    /// Reads a 64-bit field of the device configuration, which is not accessed
+    /// atomically by the legacy interface.
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        let low = self.read_config_u32(offset);
+        let high = self.read_config_u32(offset + 4);
+        u64::from(high) << 32 | u64::from(low)
+    }
+}
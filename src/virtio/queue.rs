@@ -0,0 +1,322 @@
+use core::sync::atomic::{fence, Ordering};
+use core::{mem, ptr};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::PhysAddr;
+use crate::memory;
+use super::VirtioError;
+
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+/// Alignment of the used ring in legacy virtqueues.
+const USED_RING_ALIGN: usize = 4096;
+const FRAME_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    length: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Physically contiguous buffer which is part of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub length: u32,
+    /// Set for buffers the device writes to, such as the data of a read.
+    pub device_writable: bool,
+}
+
+/// Split virtqueue in the layout of legacy virtio devices.
+#[derive(Debug)]
+pub struct Virtqueue {
+    size: u16,
+    frames: PhysFrameRange,
+    descriptors: *mut Descriptor,
+    /// Flags, index and ring of the available ring.
+    available: *mut u16,
+    /// Flags and index of the used ring, followed by its elements.
+    used: *const u16,
+    free_head: u16,
+    free_count: u16,
+    last_used: u16,
+}
+
+// the rings are only accessed through `&mut self`
+unsafe impl Send for Virtqueue {}
+
+impl Virtqueue {
+    /// Returns the number of bytes occupied by a queue with `size` entries.
+    pub fn layout_size(size: u16) -> usize {
+        let size = usize::from(size);
+        let driver_area = size * mem::size_of::<Descriptor>() + 2 * (3 + size);
+        let device_area = 2 * 3 + 8 * size;
+
+        driver_area.next_multiple_of(USED_RING_ALIGN) + device_area.next_multiple_of(USED_RING_ALIGN)
+    }
+
+    /// Allocates a queue with `size` entries in physically contiguous frames.
+    pub fn new(size: u16) -> Result<Self, VirtioError> {
+        if size == 0 || !size.is_power_of_two() {
+            return Err(VirtioError::InvalidQueueSize(size));
+        }
+
+        let layout_size = Self::layout_size(size);
+        let frames = memory::with_memory(|_, frame_allocator| {
+            frame_allocator.allocate_contiguous(layout_size / FRAME_SIZE)
+        })
+        .flatten()
+        .ok_or(VirtioError::OutOfMemory)?;
+
+        let base = memory::phys_to_virt(frames.start.start_address()).as_mut_ptr::<u8>();
+        Ok(unsafe { Self::at(size, frames, base) })
+    }
+
+    /// Sets up an empty queue in the memory at `base`, to which `frames` are
+    /// mapped.
+    ///
+    /// This function is unsafe because the caller must guarantee that `base` is
+    /// page aligned and maps `frames`, which have to hold at least
+    /// `layout_size(size)` bytes. The memory must not be used by anything else
+    /// and must stay mapped for as long as the queue exists.
+    unsafe fn at(size: u16, frames: PhysFrameRange, base: *mut u8) -> Self {
+        let layout_size = Self::layout_size(size);
+        let used_offset = (usize::from(size) * mem::size_of::<Descriptor>() + 2 * (3 + usize::from(size)))
+            .next_multiple_of(USED_RING_ALIGN);
+
+        let queue = Virtqueue {
+            size,
+            frames,
+            descriptors: base.cast(),
+            available: unsafe { base.add(usize::from(size) * mem::size_of::<Descriptor>()) }.cast(),
+            used: unsafe { base.add(used_offset) }.cast(),
+            free_head: 0,
+            free_count: size,
+            last_used: 0,
+        };
+
+        unsafe {
+            ptr::write_bytes(base, 0, layout_size);
+            for index in 0..size {
+                let descriptor = queue.descriptors.add(usize::from(index));
+                (*descriptor).next = index.wrapping_add(1);
+            }
+        }
+
+        queue
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the physical address of the queue, which is given to the device.
+    pub fn physical_address(&self) -> PhysAddr {
+        self.frames.start.start_address()
+    }
+
+    /// Makes a chain of buffers available to the device, returning the index of
+    /// its head descriptor.
+    ///
+    /// Returns `None` if there are not enough free descriptors.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > usize::from(self.free_count) {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut index = head;
+        for (position, buffer) in buffers.iter().enumerate() {
+            let descriptor = unsafe { &mut *self.descriptors.add(usize::from(index)) };
+            let last = position + 1 == buffers.len();
+
+            descriptor.address = buffer.address.as_u64();
+            descriptor.length = buffer.length;
+            descriptor.flags = if buffer.device_writable { DESCRIPTOR_WRITE } else { 0 };
+            if !last {
+                descriptor.flags |= DESCRIPTOR_NEXT;
+            }
+
+            let next = descriptor.next;
+            if last {
+                self.free_head = next;
+            }
+            index = next;
+        }
+        self.free_count -= buffers.len() as u16;
+
+        unsafe {
+            let available_index = ptr::read_volatile(self.available.add(1));
+            let slot = 2 + usize::from(available_index % self.size);
+            ptr::write_volatile(self.available.add(slot), head);
+
+            // the descriptors have to be visible before the index is published
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(self.available.add(1), available_index.wrapping_add(1));
+        }
+
+        Some(head)
+    }
+
+    /// Takes the next chain the device has finished with, returning the index of
+    /// its head descriptor and the number of bytes written by the device.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_index = unsafe { ptr::read_volatile(self.used.add(1)) };
+        if used_index == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = usize::from(self.last_used % self.size);
+        let element = unsafe { self.used.add(2).cast::<u32>().add(2 * slot) };
+        let (head, length) = unsafe { (ptr::read_volatile(element), ptr::read_volatile(element.add(1))) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = head as u16;
+        self.free_chain(head);
+        Some((head, length))
+    }
+
+    /// Returns the descriptors of a chain to the free list.
+    fn free_chain(&mut self, head: u16) {
+        let mut index = head;
+
+        loop {
+            let descriptor = unsafe { &mut *self.descriptors.add(usize::from(index)) };
+            self.free_count += 1;
+
+            if descriptor.flags & DESCRIPTOR_NEXT == 0 {
+                descriptor.next = self.free_head;
+                break;
+            }
+            index = descriptor.next;
+        }
+
+        self.free_head = head;
+    }
+}
+
+impl Drop for Virtqueue {
+    fn drop(&mut self) {
+        // the device must have been reset, so that it does not access the queue anymore
+        memory::with_memory(|_, frame_allocator| unsafe { frame_allocator.deallocate_contiguous(self.frames) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::ManuallyDrop;
+    use core::ptr;
+    use x86_64::structures::paging::PhysFrame;
+    use x86_64::PhysAddr;
+    use super::{Buffer, Virtqueue, DESCRIPTOR_NEXT, DESCRIPTOR_WRITE};
+
+    const SIZE: u16 = 4;
+
+    #[repr(align(4096))]
+    struct QueueMemory([u8; 2 * 4096]);
+
+    /// Runs `f` with a queue of `SIZE` entries in static memory.
+    fn with_queue<F: FnOnce(&mut Virtqueue)>(f: F) {
+        static mut MEMORY: QueueMemory = QueueMemory([0; 2 * 4096]);
+
+        // the frames are only given to the device, which does not exist here,
+        // and must not be freed
+        let frame = PhysFrame::containing_address(PhysAddr::new(0));
+        let frames = PhysFrame::range(frame, frame + 2);
+        let mut queue = ManuallyDrop::new(unsafe { Virtqueue::at(SIZE, frames, &raw mut MEMORY.0 as *mut u8) });
+        f(&mut queue);
+    }
+
+    fn buffer(address: u64, device_writable: bool) -> Buffer {
+        Buffer {
+            address: PhysAddr::new(address),
+            length: 16,
+            device_writable,
+        }
+    }
+
+    /// Moves the chain at `head` to the used ring, like the device would.
+    fn complete(queue: &mut Virtqueue, head: u16, length: u32) {
+        unsafe {
+            let used = queue.used.cast_mut();
+            let used_index = ptr::read_volatile(used.add(1));
+            let element = used.add(2).cast::<u32>().add(2 * usize::from(used_index % queue.size));
+
+            ptr::write_volatile(element, u32::from(head));
+            ptr::write_volatile(element.add(1), length);
+            ptr::write_volatile(used.add(1), used_index.wrapping_add(1));
+        }
+    }
+
+    #[test_case]
+    fn test_layout_size() {
+        // 4 KiB of descriptors and 518 bytes of available ring, then the used ring
+        assert_eq!(Virtqueue::layout_size(256), 3 * 4096);
+        assert_eq!(Virtqueue::layout_size(16), 2 * 4096);
+    }
+
+    #[test_case]
+    fn test_add() {
+        with_queue(|queue| {
+            let head = queue.add(&[buffer(0x1000, false), buffer(0x2000, true)]).unwrap();
+            assert_eq!(head, 0);
+            assert_eq!(queue.free_count, SIZE - 2);
+
+            let first = unsafe { *queue.descriptors };
+            let second = unsafe { *queue.descriptors.add(usize::from(first.next)) };
+            assert_eq!((first.address, first.flags), (0x1000, DESCRIPTOR_NEXT));
+            assert_eq!((second.address, second.flags), (0x2000, DESCRIPTOR_WRITE));
+
+            // the head is published in the available ring
+            let (index, slot) = unsafe { (ptr::read_volatile(queue.available.add(1)), ptr::read_volatile(queue.available.add(2))) };
+            assert_eq!((index, slot), (1, head));
+        });
+    }
+
+    #[test_case]
+    fn test_add_full() {
+        with_queue(|queue| {
+            assert_eq!(queue.add(&[]), None);
+            assert!(queue.add(&[buffer(0x1000, false); 3]).is_some());
+            assert_eq!(queue.add(&[buffer(0x1000, false); 2]), None);
+            assert!(queue.add(&[buffer(0x1000, false)]).is_some());
+            assert_eq!(queue.free_count, 0);
+        });
+    }
+
+    #[test_case]
+    fn test_pop_used() {
+        with_queue(|queue| {
+            let head = queue.add(&[buffer(0x1000, false), buffer(0x2000, true)]).unwrap();
+            assert_eq!(queue.pop_used(), None);
+
+            complete(queue, head, 16);
+            assert_eq!(queue.pop_used(), Some((head, 16)));
+            assert_eq!(queue.pop_used(), None);
+            assert_eq!(queue.free_count, SIZE);
+        });
+    }
+
+    #[test_case]
+    fn test_free_chain() {
+        with_queue(|queue| {
+            let first = queue.add(&[buffer(0x1000, false); 2]).unwrap();
+            let second = queue.add(&[buffer(0x2000, false); 2]).unwrap();
+            assert_eq!(queue.add(&[buffer(0x3000, false)]), None);
+
+            // chains finishing out of order return all of their descriptors
+            complete(queue, second, 0);
+            complete(queue, first, 0);
+            assert_eq!(queue.pop_used(), Some((second, 0)));
+            assert_eq!(queue.pop_used(), Some((first, 0)));
+            assert_eq!(queue.free_count, SIZE);
+
+            // the free list links every descriptor again
+            assert!(queue.add(&[buffer(0x4000, false); SIZE as usize]).is_some());
+        });
+    }
+}
@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::block::{ata, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Size of the scratch drive which the test arguments attach to QEMU.
+const SCRATCH_DISK_SIZE: u64 = 1024 * 1024;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+/// Returns the boot disk, which QEMU attaches as primary master.
+fn boot_disk() -> ata::AtaDrive {
+    ata::AtaDrive::identify(0, false).expect("no boot disk on the primary channel")
+}
+
+/// Returns the empty drive which QEMU attaches as primary slave, whose writes
+/// only go to a temporary snapshot.
+fn scratch_disk() -> ata::AtaDrive {
+    ata::AtaDrive::identify(0, true).expect("no scratch disk on the primary channel")
+}
+
+#[test_case]
+fn test_identify() {
+    let disk = boot_disk();
+
+    assert!(disk.block_count() > 0);
+    assert_eq!(disk.capacity(), disk.block_count() * SECTOR_SIZE as u64);
+    assert!(!disk.model().is_empty());
+}
+
+#[test_case]
+fn test_read_boot_sector() {
+    let mut disk = boot_disk();
+    let mut sector = [0; SECTOR_SIZE];
+
+    disk.read_blocks(0, &mut sector).unwrap();
+    assert_eq!(&sector[510..], &[0x55, 0xaa]);
+}
+
+#[test_case]
+fn test_scratch_disk() {
+    let mut disk = scratch_disk();
+    let mut sector = [0xff; SECTOR_SIZE];
+
+    assert_eq!(disk.capacity(), SCRATCH_DISK_SIZE);
+    disk.read_blocks(0, &mut sector).unwrap();
+    assert!(sector.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn test_write_and_restore() {
+    let mut disk = scratch_disk();
+    let last = disk.block_count() - 2;
+
+    let mut original = vec![0; 2 * SECTOR_SIZE];
+    disk.read_blocks(last, &mut original).unwrap();
+
+    let pattern: alloc::vec::Vec<u8> = (0..2 * SECTOR_SIZE).map(|i| i as u8).collect();
+    disk.write_blocks(last, &pattern).unwrap();
+    disk.flush().unwrap();
+
+    let mut read = vec![0; 2 * SECTOR_SIZE];
+    disk.read_blocks(last, &mut read).unwrap();
+    assert_eq!(read, pattern);
+
+    disk.write_blocks(last, &original).unwrap();
+    disk.flush().unwrap();
+}
+
+#[test_case]
+fn test_out_of_range() {
+    let mut disk = boot_disk();
+    let mut sector = [0; SECTOR_SIZE];
+
+    assert_eq!(disk.read_blocks(disk.block_count(), &mut sector), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read_blocks(0, &mut sector[1..]), Err(BlockError::InvalidBufferSize(SECTOR_SIZE - 1)));
+}
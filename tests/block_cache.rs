@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::block::cache::BlockCache;
+use kernel::block::{BlockDevice, BlockError};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+const BLOCK_SIZE: usize = 16;
+
+/// Device in memory which counts the blocks transferred.
+struct MemoryDevice {
+    data: Vec<u8>,
+    reads: u64,
+    writes: u64,
+}
+
+impl BlockDevice for MemoryDevice {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let offset = start as usize * BLOCK_SIZE;
+        buffer.copy_from_slice(&self.data[offset..offset + buffer.len()]);
+        self.reads += (buffer.len() / BLOCK_SIZE) as u64;
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        let offset = start as usize * BLOCK_SIZE;
+        self.data[offset..offset + buffer.len()].copy_from_slice(buffer);
+        self.writes += (buffer.len() / BLOCK_SIZE) as u64;
+        Ok(())
+    }
+}
+
+fn cache(blocks: usize, capacity: usize) -> BlockCache<MemoryDevice> {
+    let data = (0..blocks * BLOCK_SIZE).map(|byte| (byte / BLOCK_SIZE) as u8).collect();
+    BlockCache::new(MemoryDevice { data, reads: 0, writes: 0 }, capacity)
+}
+
+#[test_case]
+fn test_cache_read_ahead() {
+    let mut cache = cache(32, 8);
+    cache.set_read_ahead(2);
+    let mut buffer = [0; BLOCK_SIZE];
+
+    cache.read_blocks(0, &mut buffer).unwrap();
+    cache.read_blocks(1, &mut buffer).unwrap();
+    assert_eq!(buffer, [1; BLOCK_SIZE]);
+    cache.read_blocks(2, &mut buffer).unwrap();
+    cache.read_blocks(3, &mut buffer).unwrap();
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.read_ahead), (2, 2, 2));
+    assert_eq!(cache.device().reads, 4);
+}
+
+#[test_case]
+fn test_cache_write_back() {
+    let mut cache = cache(8, 2);
+    cache.set_read_ahead(0);
+
+    cache.write_blocks(0, &[0xaa; 2 * BLOCK_SIZE]).unwrap();
+    assert_eq!(cache.device().writes, 0);
+
+    // the least recently used block 0 is evicted and written back
+    let mut buffer = [0; BLOCK_SIZE];
+    cache.read_blocks(1, &mut buffer).unwrap();
+    cache.read_blocks(5, &mut buffer).unwrap();
+    assert_eq!(buffer, [5; BLOCK_SIZE]);
+    assert_eq!(cache.device().writes, 1);
+    assert_eq!(cache.stats().evictions, 1);
+
+    cache.sync().unwrap();
+    assert_eq!(cache.device().writes, 2);
+    assert_eq!(&cache.device().data[..2 * BLOCK_SIZE], &vec![0xaa; 2 * BLOCK_SIZE][..]);
+}
@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::allocator::debug;
+use kernel::qemu::{self, QemuExitCode};
+use kernel::{serial_print, serial_println};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+
+    test_detects_overflow();
+    test_detects_double_free();
+    test_detects_layout_mismatch();
+
+    qemu::exit(QemuExitCode::Success);
+    kernel::hlt_loop();
+}
+
+/// Runs `f` and checks that the debug allocator reported exactly one problem.
+fn expect_problem(name: &str, f: impl FnOnce()) {
+    serial_print!("debug_heap::{}...\t", name);
+
+    let problems = debug::problems();
+    f();
+    assert_eq!(debug::problems(), problems + 1);
+
+    serial_println!("[ok]");
+}
+
+fn test_detects_overflow() {
+    expect_problem("detects_overflow", || {
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc(layout);
+            ptr.add(layout.size()).write_volatile(0);
+            dealloc(ptr, layout);
+        }
+    });
+}
+
+fn test_detects_double_free() {
+    expect_problem("detects_double_free", || {
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc(layout);
+            dealloc(ptr, layout);
+            dealloc(ptr, layout);
+        }
+    });
+}
+
+fn test_detects_layout_mismatch() {
+    expect_problem("detects_layout_mismatch", || {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc(layout);
+            dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+        }
+    });
+}
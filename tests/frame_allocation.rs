@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::BootInfoFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB};
+
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe { BootInfoFrameAllocator::new(&boot_info.memory_map, memory_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+fn with_allocator<F: FnOnce(&mut BootInfoFrameAllocator)>(f: F) {
+    f(FRAME_ALLOCATOR.lock().as_mut().unwrap());
+}
+
+#[test_case]
+fn test_frame_counts() {
+    with_allocator(|allocator| {
+        assert!(allocator.total_frames() > 0);
+        assert_eq!(allocator.free_frames() + allocator.used_frames(), allocator.total_frames());
+
+        let used = allocator.used_frames();
+        let frame: PhysFrame = allocator.allocate_frame().unwrap();
+        assert_eq!(allocator.used_frames(), used + 1);
+
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.used_frames(), used);
+    });
+}
+
+#[test_case]
+fn test_freed_frame_is_reused() {
+    with_allocator(|allocator| {
+        let frame: PhysFrame = allocator.allocate_frame().unwrap();
+        unsafe { allocator.deallocate_frame(frame) };
+
+        let again: PhysFrame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame, again);
+        unsafe { allocator.deallocate_frame(again) };
+    });
+}
+
+#[test_case]
+fn test_huge_frame() {
+    with_allocator(|allocator| {
+        let used = allocator.used_frames();
+        let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+
+        assert!(frame.start_address().is_aligned(2u64 * 1024 * 1024));
+        assert_eq!(allocator.used_frames(), used + 512);
+
+        unsafe { allocator.deallocate_frame(frame) };
+        assert_eq!(allocator.used_frames(), used);
+    });
+}
+
+#[test_case]
+fn test_contiguous_frames() {
+    with_allocator(|allocator| {
+        let range = allocator.allocate_contiguous(16).unwrap();
+        assert_eq!(range.count(), 16);
+
+        unsafe { allocator.deallocate_contiguous(range) };
+    });
+}
@@ -24,10 +24,9 @@ fn main(boot_info: &'static BootInfo) -> ! {
     kernel::init();
 
     let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::get_memory_mapper(memory_offset) };
-    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::new(&boot_info.memory_map) };
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::init_heap().unwrap();
 
     test_main();
     kernel::hlt_loop();
@@ -63,4 +62,84 @@ fn test_many_boxes() {
 
         assert_eq!(*x, i);
     }
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_heap_growth() {
+    let size = 4 * 1024 * 1024;
+    let vec = alloc::vec![1u8; size];
+
+    assert!(kernel::allocator::heap_size() > size);
+    assert_eq!(vec.iter().map(|&x| x as usize).sum::<usize>(), size);
+}
+
+#[test_case]
+fn test_free_regions_coalesce() {
+    use alloc::alloc::{alloc, dealloc, Layout};
+
+    // larger than the fixed size blocks, so that the blocks are split from and
+    // merged into free regions
+    const SIZE: usize = 16 * 1024;
+    const ATTEMPTS: usize = 64;
+    let layout = Layout::from_size_align(SIZE, 8).unwrap();
+
+    // find two neighbouring blocks, the first one aligned like a buddy pair
+    let mut skipped = [core::ptr::null_mut(); 2 * ATTEMPTS];
+    let mut skipped_count = 0;
+    let mut pair = None;
+    for _ in 0..ATTEMPTS {
+        let first = unsafe { alloc(layout) };
+        assert!(!first.is_null());
+
+        if (first as usize).is_multiple_of(2 * SIZE) {
+            let second = unsafe { alloc(layout) };
+            assert!(!second.is_null());
+
+            if second as usize == first as usize + SIZE {
+                pair = Some((first, second));
+                break;
+            }
+            skipped[skipped_count] = second;
+            skipped_count += 1;
+        }
+        skipped[skipped_count] = first;
+        skipped_count += 1;
+    }
+    let (first, second) = pair.expect("no neighbouring blocks found");
+
+    // only a merged region can hold both blocks at the address of the first one
+    unsafe {
+        dealloc(first, layout);
+        dealloc(second, layout);
+    }
+    let double_layout = Layout::from_size_align(2 * SIZE, 8).unwrap();
+    let merged = unsafe { alloc(double_layout) };
+    assert_eq!(merged, first);
+
+    unsafe {
+        dealloc(merged, double_layout);
+        for &block in &skipped[..skipped_count] {
+            dealloc(block, layout);
+        }
+    }
+}
+
+#[test_case]
+fn test_stats() {
+    use kernel::allocator;
+
+    let before = allocator::stats();
+    let value = Box::new([0u64; 16]);
+    let during = allocator::stats();
+
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.bytes_in_use, before.bytes_in_use + 128);
+    assert!(during.peak_bytes_in_use >= during.bytes_in_use);
+
+    drop(value);
+    let after = allocator::stats();
+
+    assert_eq!(after.deallocations, before.deallocations + 1);
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+    assert!(after.largest_free_block > 0);
+}
@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::interrupts::{self, IrqError, MAX_HANDLERS_PER_IRQ, PIC_1_OFFSET};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    kernel::init();
+    test_main();
+    kernel::hlt_loop();
+}
+
+/// Enters the IDT entry of the given IRQ like the interrupt controller would,
+/// without the IRQ being in service.
+macro_rules! raise_irq {
+    ($irq:literal) => {
+        unsafe { core::arch::asm!("int {vector}", vector = const PIC_1_OFFSET + $irq) }
+    };
+}
+
+#[test_case]
+fn test_multiple_handlers() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static FIRST: AtomicUsize = AtomicUsize::new(0);
+    static SECOND: AtomicUsize = AtomicUsize::new(0);
+
+    interrupts::register_irq(5, |_| FIRST.store(CALLS.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst)).unwrap();
+    interrupts::register_irq(5, |_| SECOND.store(CALLS.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst)).unwrap();
+
+    raise_irq!(5);
+
+    // handlers run in the order of registration
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    assert_eq!(FIRST.load(Ordering::SeqCst), 1);
+    assert_eq!(SECOND.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn test_line_full() {
+    for _ in 0..MAX_HANDLERS_PER_IRQ {
+        interrupts::register_irq(6, |_| {}).unwrap();
+    }
+
+    assert_eq!(interrupts::register_irq(6, |_| {}), Err(IrqError::LineFull(6)));
+}
+
+#[test_case]
+fn test_invalid_irq() {
+    assert_eq!(interrupts::register_irq(16, |_| {}), Err(IrqError::InvalidIrq(16)));
+    assert_eq!(interrupts::mask_irq(16), Err(IrqError::InvalidIrq(16)));
+    assert_eq!(interrupts::unmask_irq(16), Err(IrqError::InvalidIrq(16)));
+    assert_eq!(interrupts::is_irq_masked(16), Err(IrqError::InvalidIrq(16)));
+}
+
+#[test_case]
+fn test_mask_unmask() {
+    assert_eq!(interrupts::is_irq_masked(3), Ok(true));
+
+    interrupts::unmask_irq(3).unwrap();
+    assert_eq!(interrupts::is_irq_masked(3), Ok(false));
+
+    interrupts::mask_irq(3).unwrap();
+    assert_eq!(interrupts::is_irq_masked(3), Ok(true));
+}
+
+#[test_case]
+fn test_unmask_secondary_unmasks_cascade() {
+    interrupts::mask_irq(2).unwrap();
+
+    interrupts::unmask_irq(9).unwrap();
+    assert_eq!(interrupts::is_irq_masked(9), Ok(false));
+    assert_eq!(interrupts::is_irq_masked(2), Ok(false));
+
+    interrupts::mask_irq(9).unwrap();
+}
+
+#[test_case]
+fn test_spurious_irqs() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    interrupts::register_irq(7, |_| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    })
+    .unwrap();
+    interrupts::register_irq(15, |_| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    })
+    .unwrap();
+
+    // neither IRQ is in service, so both are taken as spurious
+    raise_irq!(7);
+    raise_irq!(15);
+
+    assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+}
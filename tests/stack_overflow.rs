@@ -43,7 +43,7 @@ extern "x86-interrupt" fn test_double_fault_handler(_stack_frame: InterruptStack
     use kernel::qemu;
     serial_println!("[ok]");
     qemu::exit(qemu::QemuExitCode::Success);
-    loop {}
+    kernel::hlt_loop();
 }
 
 fn test_should_overflow() {
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use kernel::thread;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn increment_counter() {
+    COUNTER.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn test_boot_thread_id() {
+    assert_eq!(thread::current_thread_id(), thread::ThreadId::BOOT);
+}
+
+#[test_case]
+fn test_spawned_threads_run() {
+    let n = 4;
+
+    for _ in 0..n {
+        thread::spawn(increment_counter);
+    }
+
+    while COUNTER.load(Ordering::SeqCst) < n {
+        thread::yield_now();
+    }
+
+    assert_eq!(COUNTER.load(Ordering::SeqCst), n);
+}
+
+#[test_case]
+fn test_preemption() {
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static STOPPED: AtomicBool = AtomicBool::new(false);
+
+    fn spin_until_stopped() {
+        RUNNING.store(true, Ordering::SeqCst);
+        while !STOP.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+        STOPPED.store(true, Ordering::SeqCst);
+    }
+
+    thread::spawn(spin_until_stopped);
+
+    // never yields explicitly, so the thread only runs if the timer preempts us
+    while !RUNNING.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+
+    // let the thread exit, so that it does not keep running during later tests
+    STOP.store(true, Ordering::SeqCst);
+    while !STOPPED.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+}
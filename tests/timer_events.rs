@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel::time::{self, Duration, Instant};
+use kernel::timer;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, apic, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+
+    // events still run on the periodic timer if the HPET is missing
+    if apic::init().is_ok() && time::hpet::init().is_ok() {
+        timer::init().unwrap();
+    }
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn test_schedule_at() {
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    let deadline = Instant::now() + Duration::from_millis(50);
+    timer::schedule_at(deadline, || FIRED.store(true, Ordering::SeqCst));
+
+    time::sleep(Duration::from_millis(5));
+    assert!(!FIRED.load(Ordering::SeqCst));
+
+    time::sleep(Duration::from_millis(100));
+    assert!(FIRED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_cancel() {
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    let id = timer::schedule_after(Duration::from_millis(10), || FIRED.store(true, Ordering::SeqCst));
+    assert!(timer::cancel(id));
+
+    time::sleep(Duration::from_millis(30));
+    assert!(!FIRED.load(Ordering::SeqCst));
+    assert!(!timer::cancel(id));
+}
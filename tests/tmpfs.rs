@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::vfs::tmpfs::Tmpfs;
+use kernel::vfs::{self, FileType, SeekFrom, VfsError, OPEN_CREATE, OPEN_READ, OPEN_TRUNCATE, OPEN_WRITE};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+    vfs::mount("/", Arc::new(Tmpfs::new(64 * 1024))).unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn test_file_operations() {
+    let mut file = vfs::open("/file", OPEN_READ | OPEN_WRITE | OPEN_CREATE).unwrap();
+    assert_eq!(file.write(b"hello world").unwrap(), 11);
+
+    let mut buffer = [0; 8];
+    assert_eq!(file.seek(SeekFrom::End(-5)).unwrap(), 6);
+    assert_eq!(file.read(&mut buffer).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"world");
+    assert_eq!(file.read(&mut buffer).unwrap(), 0);
+    assert_eq!(file.seek(SeekFrom::Current(-20)), Err(VfsError::InvalidSeek));
+
+    let mut file = vfs::open("/file", OPEN_WRITE | OPEN_TRUNCATE).unwrap();
+    assert_eq!(vfs::stat("/file").unwrap().size, 0);
+    assert_eq!(file.read(&mut buffer), Err(VfsError::PermissionDenied));
+
+    vfs::unlink("/file").unwrap();
+    assert_eq!(vfs::stat("/file"), Err(VfsError::NotFound));
+}
+
+#[test_case]
+fn test_directories() {
+    vfs::mkdir("/dir").unwrap();
+    vfs::mkdir("/dir/sub").unwrap();
+    vfs::open("/dir/sub/../a", OPEN_CREATE).unwrap();
+    assert_eq!(vfs::mkdir("/dir/a"), Err(VfsError::AlreadyExists));
+
+    let names: Vec<_> = vfs::read_dir("/dir").unwrap().into_iter().map(|entry| entry.name).collect();
+    assert_eq!(names, ["a", "sub"]);
+    assert_eq!(vfs::stat("/dir/./sub").unwrap().file_type, FileType::Directory);
+    assert_eq!(vfs::stat("/dir/a/b"), Err(VfsError::NotADirectory));
+
+    vfs::rename("/dir/a", "/dir/sub/b").unwrap();
+    assert_eq!(vfs::stat("/dir/sub/b").unwrap().file_type, FileType::File);
+    assert_eq!(vfs::rename("/dir", "/dir/sub/c"), Err(VfsError::InvalidPath));
+    assert_eq!(vfs::unlink("/dir"), Err(VfsError::DirectoryNotEmpty));
+
+    vfs::unlink("/dir/sub/b").unwrap();
+    vfs::unlink("/dir/sub").unwrap();
+    vfs::unlink("/dir").unwrap();
+}
+
+#[test_case]
+fn test_mounts() {
+    vfs::mkdir("/mnt").unwrap();
+    vfs::mount("/mnt", Arc::new(Tmpfs::new(1024))).unwrap();
+    vfs::open("/mnt/file", OPEN_CREATE).unwrap();
+
+    assert!(vfs::read_dir("/").unwrap().iter().all(|entry| entry.name != "file"));
+    assert_eq!(vfs::rename("/mnt/file", "/file"), Err(VfsError::CrossDevice));
+    assert_eq!(vfs::unlink("/mnt"), Err(VfsError::Busy));
+    assert_eq!(vfs::rename("/mnt", "/other"), Err(VfsError::Busy));
+
+    vfs::unmount("/mnt").unwrap();
+    assert_eq!(vfs::stat("/mnt/file"), Err(VfsError::NotFound));
+    vfs::unlink("/mnt").unwrap();
+}
+
+#[test_case]
+fn test_tmpfs_limit() {
+    let tmpfs = Arc::new(Tmpfs::new(16));
+    vfs::mkdir("/small").unwrap();
+    vfs::mount("/small", tmpfs.clone()).unwrap();
+
+    let mut file = vfs::open("/small/file", OPEN_WRITE | OPEN_CREATE).unwrap();
+    assert_eq!(file.write(&[1; 16]).unwrap(), 16);
+    assert_eq!(file.write(&[1]), Err(VfsError::NoSpace));
+    assert_eq!(tmpfs.used(), 16);
+
+    // the data is freed once the unlinked file is closed
+    vfs::unlink("/small/file").unwrap();
+    assert_eq!(tmpfs.used(), 16);
+    drop(file);
+    assert_eq!(tmpfs.used(), 0);
+
+    vfs::unmount("/small").unwrap();
+    vfs::unlink("/small").unwrap();
+}
@@ -0,0 +1,250 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::vfs::path::{normalize, split_parent};
+use kernel::vfs::{
+    self, DirEntry, FileSystem, FileType, Inode, Metadata, SeekFrom, VfsError, OPEN_CREATE, OPEN_READ,
+    OPEN_TRUNCATE, OPEN_WRITE,
+};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+    vfs::mount("/", Arc::new(StubFs::new(1))).unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn test_normalize() {
+    assert_eq!(normalize("/").unwrap(), [""; 0]);
+    assert_eq!(normalize("/a/./b//c/").unwrap(), ["a", "b", "c"]);
+    assert_eq!(normalize("/a/b/../../../c").unwrap(), ["c"]);
+    assert!(normalize("a/b").is_err());
+
+    let (parent, name) = split_parent("/mnt/disk/../file").unwrap();
+    assert_eq!((&parent[..], name), (&["mnt"][..], "file"));
+    assert!(split_parent("/..").is_err());
+}
+
+/// Contents of the file `data` in the root of a `StubFs`.
+const STUB_DATA: &[u8] = b"0123456789";
+
+/// Read-only inode of a `StubFs`.
+struct StubInode {
+    /// Inode number of the root of the file system.
+    root: u64,
+    inode: u64,
+    file_type: FileType,
+}
+
+impl Inode for StubInode {
+    fn metadata(&self) -> Result<Metadata, VfsError> {
+        let size = match self.file_type {
+            FileType::File => STUB_DATA.len(),
+            FileType::Directory => self.read_dir()?.len(),
+        };
+
+        Ok(Metadata {
+            inode: self.inode,
+            file_type: self.file_type,
+            size: size as u64,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        let available = STUB_DATA.get(offset as usize..).unwrap_or_default();
+        let length = buffer.len().min(available.len());
+
+        buffer[..length].copy_from_slice(&available[..length]);
+        Ok(length)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, VfsError> {
+        let entry = self.read_dir()?.into_iter().find(|entry| entry.name == name).ok_or(VfsError::NotFound)?;
+
+        Ok(Arc::new(StubInode {
+            root: self.root,
+            inode: entry.inode,
+            file_type: entry.file_type,
+        }))
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, VfsError> {
+        if self.file_type != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+
+        // only the root has entries, its directory is empty
+        if self.inode != self.root {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![
+            DirEntry {
+                name: String::from("data"),
+                inode: self.root + 1,
+                file_type: FileType::File,
+            },
+            DirEntry {
+                name: String::from("dir"),
+                inode: self.root + 2,
+                file_type: FileType::Directory,
+            },
+        ])
+    }
+}
+
+/// File system with a fixed root holding the file `data` and the empty
+/// directory `dir`, which counts how often it is synced.
+struct StubFs {
+    root: u64,
+    syncs: AtomicUsize,
+}
+
+impl StubFs {
+    /// Creates a file system whose inode numbers start at `root`.
+    fn new(root: u64) -> Self {
+        StubFs {
+            root,
+            syncs: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl FileSystem for StubFs {
+    fn name(&self) -> &'static str {
+        "stub"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(StubInode {
+            root: self.root,
+            inode: self.root,
+            file_type: FileType::Directory,
+        })
+    }
+
+    fn sync(&self) -> Result<(), VfsError> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_mount_lookup() {
+    assert_eq!(vfs::stat("/").unwrap().inode, 1);
+    assert_eq!(vfs::stat("/data").unwrap().size, STUB_DATA.len() as u64);
+    assert_eq!(vfs::stat("/dir/../data").unwrap().inode, 2);
+    assert_eq!(vfs::stat("/missing"), Err(VfsError::NotFound));
+    assert_eq!(vfs::stat("/data/file"), Err(VfsError::NotADirectory));
+
+    // the longest mount point wins
+    let nested = Arc::new(StubFs::new(10));
+    vfs::mount("/dir", nested.clone()).unwrap();
+    assert!(vfs::mounts().contains(&(String::from("/dir"), "stub")));
+    assert_eq!(vfs::stat("/dir").unwrap().inode, 10);
+    assert_eq!(vfs::stat("/dir/data").unwrap().inode, 11);
+    assert_eq!(vfs::stat("/dir/dir/../../data").unwrap().inode, 2);
+
+    assert_eq!(vfs::unmount("/"), Err(VfsError::Busy));
+    vfs::unmount("/dir").unwrap();
+    assert_eq!(nested.syncs.load(Ordering::SeqCst), 1);
+    assert_eq!(vfs::stat("/dir").unwrap().inode, 3);
+    assert_eq!(vfs::stat("/dir/data"), Err(VfsError::NotFound));
+    assert_eq!(vfs::unmount("/dir"), Err(VfsError::InvalidPath));
+}
+
+#[test_case]
+fn test_mount_busy() {
+    assert_eq!(vfs::mount("/", Arc::new(StubFs::new(10))), Err(VfsError::Busy));
+    assert_eq!(vfs::mount("/data", Arc::new(StubFs::new(10))), Err(VfsError::NotADirectory));
+
+    vfs::mount("/dir", Arc::new(StubFs::new(10))).unwrap();
+    assert_eq!(vfs::mount("/dir", Arc::new(StubFs::new(20))), Err(VfsError::Busy));
+    assert_eq!(vfs::unlink("/dir"), Err(VfsError::Busy));
+    vfs::unmount("/dir").unwrap();
+
+    // without the mount the request reaches the file system
+    assert_eq!(vfs::unlink("/dir"), Err(VfsError::Unsupported));
+}
+
+#[test_case]
+fn test_open_flags() {
+    let mut file = vfs::open("/data", OPEN_READ).unwrap();
+    assert_eq!(file.write(b"x"), Err(VfsError::PermissionDenied));
+    drop(file);
+
+    let mut file = vfs::open("/data", OPEN_WRITE).unwrap();
+    assert_eq!(file.read(&mut [0; 4]), Err(VfsError::PermissionDenied));
+    assert_eq!(file.write(b"x"), Err(VfsError::Unsupported));
+    drop(file);
+
+    assert!(vfs::open("/dir", OPEN_READ).is_ok());
+    assert!(matches!(vfs::open("/dir", OPEN_WRITE), Err(VfsError::IsADirectory)));
+    assert!(matches!(vfs::open("/dir", OPEN_TRUNCATE), Err(VfsError::IsADirectory)));
+    assert!(matches!(vfs::open("/data", OPEN_TRUNCATE), Err(VfsError::PermissionDenied)));
+    assert!(matches!(vfs::open("/data", OPEN_WRITE | OPEN_TRUNCATE), Err(VfsError::Unsupported)));
+    assert!(matches!(vfs::open("/missing", OPEN_READ), Err(VfsError::NotFound)));
+    assert!(matches!(vfs::open("/missing", OPEN_CREATE), Err(VfsError::Unsupported)));
+}
+
+#[test_case]
+fn test_seek() {
+    let mut file = vfs::open("/data", OPEN_READ).unwrap();
+    let mut buffer = [0; 3];
+
+    assert_eq!(file.seek(SeekFrom::Start(3)).unwrap(), 3);
+    assert_eq!(file.read(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer, b"345");
+
+    assert_eq!(file.seek(SeekFrom::Current(-2)).unwrap(), 4);
+    assert_eq!(file.seek(SeekFrom::End(-2)).unwrap(), 8);
+    assert_eq!(file.read(&mut buffer).unwrap(), 2);
+    assert_eq!(&buffer[..2], b"89");
+
+    // seeking beyond the end is allowed, before the start is not
+    assert_eq!(file.seek(SeekFrom::End(5)).unwrap(), 15);
+    assert_eq!(file.read(&mut buffer).unwrap(), 0);
+    assert_eq!(file.seek(SeekFrom::Current(-16)), Err(VfsError::InvalidSeek));
+    assert_eq!(file.seek(SeekFrom::Start(0)).unwrap(), 0);
+}
@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::block::{virtio, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Size of the drive which the test arguments attach to QEMU.
+const DISK_SIZE: u64 = 1024 * 1024;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::{allocator, memory, pci};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+
+    let memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { memory::init(memory_offset, &boot_info.memory_map) };
+
+    allocator::init_heap().unwrap();
+    pci::init().unwrap();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+/// Returns the empty drive which QEMU attaches as virtio-blk device.
+fn disk() -> virtio::VirtioBlk {
+    virtio::probe().pop().expect("no virtio-blk device found")
+}
+
+#[test_case]
+fn test_probe() {
+    let disk = disk();
+
+    assert_eq!(disk.block_count() * SECTOR_SIZE as u64, DISK_SIZE);
+    assert!(!disk.read_only());
+}
+
+#[test_case]
+fn test_read_first_sector() {
+    let mut disk = disk();
+    let mut sector = [0xff; SECTOR_SIZE];
+
+    disk.read_blocks(0, &mut sector).unwrap();
+    assert!(sector.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn test_write_and_restore() {
+    let mut disk = disk();
+    // larger than the bounce buffer, so that it takes several requests
+    let length = 160 * SECTOR_SIZE;
+    let start = disk.block_count() - 160;
+
+    let mut original = vec![0; length];
+    disk.read_blocks(start, &mut original).unwrap();
+
+    let pattern: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+    disk.write_blocks(start, &pattern).unwrap();
+    disk.flush().unwrap();
+
+    let mut read = vec![0; length];
+    disk.read_blocks(start, &mut read).unwrap();
+    assert_eq!(read, pattern);
+
+    disk.write_blocks(start, &original).unwrap();
+    disk.flush().unwrap();
+}
+
+#[test_case]
+fn test_interrupts_disabled() {
+    use x86_64::instructions::interrupts;
+
+    let mut disk = disk();
+    let mut sector = [0; SECTOR_SIZE];
+
+    // the request completes by polling and leaves interrupts disabled
+    interrupts::without_interrupts(|| {
+        disk.read_blocks(0, &mut sector).unwrap();
+        assert!(!interrupts::are_enabled());
+    });
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_out_of_range() {
+    let mut disk = disk();
+    let mut sector = [0; SECTOR_SIZE];
+
+    assert_eq!(disk.read_blocks(disk.block_count(), &mut sector), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read_blocks(0, &mut sector[1..]), Err(BlockError::InvalidBufferSize(SECTOR_SIZE - 1)));
+}